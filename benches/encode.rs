@@ -0,0 +1,52 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use tokio_util::{
+    bytes::{Bytes, BytesMut},
+    codec::Encoder,
+};
+
+use chunkio::{ChunkIOBuilder, ChunkIOProto};
+
+// Chunks encoded per iteration, so that clearing the buffer does not dominate.
+const BATCH: usize = 1024;
+const PAYLOAD_SIZES: [usize; 4] = [0, 16, 64, 256];
+
+// Encodes a batch of payloads prepared outside the timed part, so that only
+// the encoder is measured.
+fn bench_batch<P>(b: &mut criterion::Bencher, payload: &P, size: usize)
+where
+    P: Clone,
+    ChunkIOProto: Encoder<P>,
+    <ChunkIOProto as Encoder<P>>::Error: std::fmt::Debug,
+{
+    let mut codec = ChunkIOBuilder::new().codec();
+    let mut dst = BytesMut::with_capacity(BATCH * (size + 16));
+    b.iter_batched(
+        || vec![payload.clone(); BATCH],
+        |payloads| {
+            dst.clear();
+            for payload in payloads {
+                codec.encode(payload, &mut dst).unwrap();
+            }
+        },
+        BatchSize::SmallInput,
+    );
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    for size in PAYLOAD_SIZES {
+        let payload = vec![0x5a; size];
+        group.bench_with_input(BenchmarkId::new("vec", size), &payload, |b, payload| {
+            bench_batch(b, payload, size)
+        });
+        let payload = Bytes::from(payload);
+        group.bench_with_input(BenchmarkId::new("bytes", size), &payload, |b, payload| {
+            bench_batch(b, payload, size)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode);
+criterion_main!(benches);
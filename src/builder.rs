@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::bytes::Bytes;
+
+use crate::{
+    checksum::Checksum,
+    chunk::Chunk,
+    cipher::{Cipher, Encryption},
+    compression::Compression,
+    fragment::DEFAULT_MAX_MESSAGE_LENGTH,
+    negotiate::Capabilities,
+    proto::{ChunkIOProto, DEFAULT_MAX_CHUNK_LENGTH},
+    ChunkIO,
+};
+
+pub(crate) const DEFAULT_BUFFER_CAPACITY: usize = 8 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct ChunkIOBuilder {
+    pub(crate) max_chunk_length: u64,
+    pub(crate) read_buffer_capacity: usize,
+    pub(crate) write_buffer_capacity: usize,
+    pub(crate) strict: bool,
+    pub(crate) keepalive: Option<(Duration, Duration)>,
+    pub(crate) reorder_capacity: Option<usize>,
+    pub(crate) checksum: Checksum,
+    pub(crate) encryption: Option<Encryption>,
+    pub(crate) compression: Compression,
+    pub(crate) max_decompressed_length: u64,
+    pub(crate) fragment_size: Option<usize>,
+    pub(crate) max_message_length: u64,
+    pub(crate) capabilities: Capabilities,
+    pub(crate) end_of_stream: bool,
+}
+
+impl Default for ChunkIOBuilder {
+    fn default() -> Self {
+        ChunkIOBuilder::new()
+    }
+}
+
+impl ChunkIOBuilder {
+    pub fn new() -> ChunkIOBuilder {
+        ChunkIOBuilder {
+            max_chunk_length: DEFAULT_MAX_CHUNK_LENGTH,
+            read_buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+            write_buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+            strict: false,
+            keepalive: None,
+            reorder_capacity: None,
+            checksum: Checksum::None,
+            encryption: None,
+            compression: Compression::None,
+            max_decompressed_length: DEFAULT_MAX_CHUNK_LENGTH,
+            fragment_size: None,
+            max_message_length: DEFAULT_MAX_MESSAGE_LENGTH,
+            capabilities: Capabilities::default(),
+            end_of_stream: true,
+        }
+    }
+
+    /// Largest payload accepted from the peer or sent to it.
+    pub fn max_chunk_length(mut self, max_chunk_length: u64) -> Self {
+        self.max_chunk_length = max_chunk_length;
+        self
+    }
+
+    pub fn read_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.read_buffer_capacity = capacity;
+        self
+    }
+
+    pub fn write_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.write_buffer_capacity = capacity;
+        self
+    }
+
+    /// Rejects headers whose index or length fields carry leading zero bytes.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sends a ping after `interval` without receiving anything and fails the
+    /// stream with [`ChunkIOError::Timeout`](crate::ChunkIOError::Timeout) if
+    /// nothing arrives within `timeout` of it.
+    pub fn keepalive(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.keepalive = Some((interval, timeout));
+        self
+    }
+
+    /// Accepts chunks ahead of the receive offset instead of failing with
+    /// [`ChunkIOError::OutOfOrder`](crate::ChunkIOError::OutOfOrder), holding
+    /// up to `capacity` bytes of them until the gap is filled. Duplicates of
+    /// chunks already received are dropped.
+    pub fn reorder_buffer(mut self, capacity: usize) -> Self {
+        self.reorder_capacity = Some(capacity);
+        self
+    }
+
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Seals every payload with `cipher`, using `send_key` for outgoing chunks
+    /// and `recv_key` for incoming ones; the peer must use the same keys the
+    /// other way round. The nonce is the chunk's offset and the header is
+    /// authenticated along with the payload. Control frames are authenticated
+    /// as well, each under a sequence number it carries.
+    pub fn encryption(mut self, cipher: Cipher, send_key: [u8; 32], recv_key: [u8; 32]) -> Self {
+        self.encryption = Some(Encryption::new(cipher, &send_key, &recv_key));
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Largest payload a compressed chunk from the peer may expand to.
+    pub fn max_decompressed_length(mut self, max_decompressed_length: u64) -> Self {
+        self.max_decompressed_length = max_decompressed_length;
+        self
+    }
+
+    /// Sends messages larger than `fragment_size` as several chunks of at
+    /// most that size, which the peer joins back into one item. Each
+    /// fragment is compressed, sealed and checksummed on its own.
+    pub fn fragment_size(mut self, fragment_size: usize) -> Self {
+        assert!(fragment_size > 0, "fragment size must not be zero");
+        self.fragment_size = Some(fragment_size);
+        self
+    }
+
+    /// Largest message the peer may send in fragments.
+    pub fn max_message_length(mut self, max_message_length: u64) -> Self {
+        self.max_message_length = max_message_length;
+        self
+    }
+
+    /// Features offered by [`Negotiated::exchange`](crate::Negotiated::exchange).
+    /// The `negotiate` constructors decide `mux` and `encryption` themselves.
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Sends an end-of-stream frame when the sink is closed, so the peer can
+    /// tell a finished stream from a broken one. Peers from before protocol
+    /// version 2 reject the frame; [`Negotiated::apply`](crate::Negotiated::apply)
+    /// turns it off for them.
+    pub fn end_of_stream(mut self, end_of_stream: bool) -> Self {
+        self.end_of_stream = end_of_stream;
+        self
+    }
+
+    pub fn codec(&self) -> ChunkIOProto {
+        ChunkIOProto::from_builder(self)
+    }
+
+    pub fn build<T>(&self, io: T) -> ChunkIO<T>
+    where
+        T: AsyncRead + AsyncWrite,
+    {
+        ChunkIO::from_builder(io, self)
+    }
+
+    pub fn build_bytes<T>(&self, io: T) -> ChunkIO<T, Bytes>
+    where
+        T: AsyncRead + AsyncWrite,
+    {
+        ChunkIO::from_builder(io, self)
+    }
+
+    pub fn build_chunks<T>(&self, io: T) -> ChunkIO<T, Chunk>
+    where
+        T: AsyncRead + AsyncWrite,
+    {
+        ChunkIO::from_builder(io, self)
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    io,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{ready, Sink, Stream};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::bytes::{Buf, Bytes};
+
+use crate::ChunkIOError;
+
+/// Largest chunk a single write is turned into.
+pub const DEFAULT_WRITE_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Byte stream over a chunk stream such as [`ChunkIO`](crate::ChunkIO) or a
+/// [`MuxStream`](crate::MuxStream). Writes become chunks of at most the
+/// configured size and reads drain chunk payloads in order, so anything
+/// built on `AsyncRead` and `AsyncWrite` runs over it unchanged. Shutting
+/// down the writer closes the underlying sink.
+///
+/// `I` is the item type of the chunk stream. Only reading needs a `Stream`
+/// and only writing a `Sink`, so one half of a split stream works too.
+pub struct ByteStream<S, I = Vec<u8>> {
+    inner: S,
+    read_buf: Bytes,
+    write_chunk_size: usize,
+    _item: PhantomData<fn(I) -> I>,
+}
+
+impl<S, I> ByteStream<S, I> {
+    pub fn new(inner: S) -> ByteStream<S, I> {
+        ByteStream::with_write_chunk_size(inner, DEFAULT_WRITE_CHUNK_SIZE)
+    }
+
+    pub fn with_write_chunk_size(inner: S, write_chunk_size: usize) -> ByteStream<S, I> {
+        assert!(write_chunk_size > 0, "write chunk size must not be zero");
+        ByteStream {
+            inner,
+            read_buf: Bytes::new(),
+            write_chunk_size,
+            _item: PhantomData,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Returns the chunk stream; the unread rest of the current chunk is
+    /// discarded.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, I> AsyncBufRead for ByteStream<S, I>
+where
+    S: Stream<Item = Result<I, ChunkIOError>> + Unpin,
+    I: Into<Bytes>,
+{
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        // Empty chunks carry nothing to read, so they are skipped.
+        while this.read_buf.is_empty() {
+            match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(chunk) => this.read_buf = chunk.map_err(io::Error::from)?.into(),
+                None => break,
+            }
+        }
+        Poll::Ready(Ok(&this.read_buf))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.read_buf.advance(amt);
+    }
+}
+
+impl<S, I> AsyncRead for ByteStream<S, I>
+where
+    S: Stream<Item = Result<I, ChunkIOError>> + Unpin,
+    I: Into<Bytes>,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let available = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = available.len().min(buf.remaining());
+        buf.put_slice(&available[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S, I> AsyncWrite for ByteStream<S, I>
+where
+    S: Sink<I, Error = ChunkIOError> + Unpin,
+    I: From<Vec<u8>>,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(Pin::new(&mut self.inner).poll_ready(cx)).map_err(io::Error::from)?;
+        let len = buf.len().min(self.write_chunk_size);
+        Pin::new(&mut self.inner)
+            .start_send(I::from(buf[..len].to_vec()))
+            .map_err(io::Error::from)?;
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner)
+            .poll_flush(cx)
+            .map_err(io::Error::from)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner)
+            .poll_close(cx)
+            .map_err(io::Error::from)
+    }
+}
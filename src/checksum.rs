@@ -0,0 +1,122 @@
+use tokio_util::bytes::BytesMut;
+
+/// Integrity trailer appended to every data chunk and control frame. It
+/// covers the header as well as the payload, so a corrupted offset or length
+/// is caught too. Both peers must use the same setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Checksum {
+    #[default]
+    None,
+    /// 4-byte CRC-32C trailer.
+    #[cfg(feature = "crc32c")]
+    Crc32c,
+    /// 8-byte XXH3-64 trailer.
+    #[cfg(feature = "xxhash")]
+    Xxh3,
+}
+
+impl Checksum {
+    /// Every checksum compiled in, in the order of their wire ids.
+    pub(crate) const SUPPORTED: &'static [Checksum] = &[
+        Checksum::None,
+        #[cfg(feature = "crc32c")]
+        Checksum::Crc32c,
+        #[cfg(feature = "xxhash")]
+        Checksum::Xxh3,
+    ];
+
+    /// Identifies the checksum during negotiation.
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            Checksum::None => 0,
+            #[cfg(feature = "crc32c")]
+            Checksum::Crc32c => 1,
+            #[cfg(feature = "xxhash")]
+            Checksum::Xxh3 => 2,
+        }
+    }
+
+    /// Length of the trailer in bytes.
+    pub fn trailer_len(&self) -> usize {
+        match self {
+            Checksum::None => 0,
+            #[cfg(feature = "crc32c")]
+            Checksum::Crc32c => 4,
+            #[cfg(feature = "xxhash")]
+            Checksum::Xxh3 => 8,
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub(crate) fn compute(&self, header: &[u8], payload: &[u8]) -> u64 {
+        match self {
+            Checksum::None => 0,
+            #[cfg(feature = "crc32c")]
+            Checksum::Crc32c => crc32c::crc32c_append(crc32c::crc32c(header), payload) as u64,
+            #[cfg(feature = "xxhash")]
+            Checksum::Xxh3 => {
+                let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+                hasher.update(header);
+                hasher.update(payload);
+                hasher.digest()
+            }
+        }
+    }
+
+    pub(crate) fn put(&self, sum: u64, dst: &mut BytesMut) {
+        dst.extend_from_slice(&sum.to_be_bytes()[8 - self.trailer_len()..]);
+    }
+
+    pub(crate) fn verify(&self, header: &[u8], payload: &[u8], trailer: &[u8]) -> bool {
+        self.trailer_len() == 0 || crate::proto::read_be(trailer) == self.compute(header, payload)
+    }
+
+    /// Starts a checksum over a chunk whose payload arrives in pieces.
+    #[allow(unused_variables)]
+    pub(crate) fn digest(&self, header: &[u8]) -> Digest {
+        match self {
+            Checksum::None => Digest::None,
+            #[cfg(feature = "crc32c")]
+            Checksum::Crc32c => Digest::Crc32c(crc32c::crc32c(header)),
+            #[cfg(feature = "xxhash")]
+            Checksum::Xxh3 => {
+                let mut hasher = Box::new(xxhash_rust::xxh3::Xxh3::new());
+                hasher.update(header);
+                Digest::Xxh3(hasher)
+            }
+        }
+    }
+}
+
+/// Running checksum of a chunk, started by [`Checksum::digest`].
+pub(crate) enum Digest {
+    None,
+    #[cfg(feature = "crc32c")]
+    Crc32c(u32),
+    #[cfg(feature = "xxhash")]
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl Digest {
+    #[allow(unused_variables)]
+    pub(crate) fn update(&mut self, piece: &[u8]) {
+        match self {
+            Digest::None => {}
+            #[cfg(feature = "crc32c")]
+            Digest::Crc32c(sum) => *sum = crc32c::crc32c_append(*sum, piece),
+            #[cfg(feature = "xxhash")]
+            Digest::Xxh3(hasher) => hasher.update(piece),
+        }
+    }
+
+    #[allow(unused_variables)]
+    pub(crate) fn verify(&self, trailer: &[u8]) -> bool {
+        match self {
+            Digest::None => true,
+            #[cfg(feature = "crc32c")]
+            Digest::Crc32c(sum) => crate::proto::read_be(trailer) == *sum as u64,
+            #[cfg(feature = "xxhash")]
+            Digest::Xxh3(hasher) => crate::proto::read_be(trailer) == hasher.digest(),
+        }
+    }
+}
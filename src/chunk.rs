@@ -0,0 +1,156 @@
+use tokio_util::bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::ChunkIOError;
+
+const MAX_METADATA_ENTRIES: usize = u8::MAX as usize;
+const MAX_KEY_LENGTH: usize = u8::MAX as usize;
+const MAX_VALUE_LENGTH: usize = u16::MAX as usize;
+
+/// A payload with an application-defined kind and key/value metadata.
+///
+/// Kind and metadata travel in front of the payload, so they are
+/// checksummed, compressed and encrypted with it and count towards the byte
+/// offsets. A chunk of kind 0 without metadata goes out as a plain chunk,
+/// which keeps peers that send and receive `Vec<u8>` or `Bytes` compatible;
+/// those only see the payload of chunks that have more. [`ResumableChunkIO`]
+/// and [`Mux`] streams frame their items themselves and carry only the
+/// payload.
+///
+/// [`ResumableChunkIO`]: crate::ResumableChunkIO
+/// [`Mux`]: crate::Mux
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Chunk {
+    pub kind: u16,
+    /// At most 255 entries, with keys of up to 255 bytes and values of up
+    /// to 65535 bytes.
+    pub metadata: Vec<(String, Bytes)>,
+    pub payload: Bytes,
+}
+
+impl Chunk {
+    pub fn new(payload: impl Into<Bytes>) -> Chunk {
+        Chunk {
+            payload: payload.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_kind(mut self, kind: u16) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<Bytes>) -> Self {
+        self.metadata.push((key.into(), value.into()));
+        self
+    }
+
+    /// Returns the value of the first entry named `key`.
+    pub fn get(&self, key: &str) -> Option<&Bytes> {
+        self.metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    pub(crate) fn has_section(&self) -> bool {
+        self.kind != 0 || !self.metadata.is_empty()
+    }
+
+    fn section_len(&self) -> usize {
+        if !self.has_section() {
+            return 0;
+        }
+        self.metadata
+            .iter()
+            .fold(3, |len, (key, value)| len + 3 + key.len() + value.len())
+    }
+
+    /// Number of bytes the chunk advances the stream offset by.
+    pub(crate) fn stream_len(&self) -> usize {
+        self.section_len() + self.payload.len()
+    }
+
+    /// Kind and metadata as written in front of the payload.
+    pub(crate) fn encode_section(&self, dst: &mut BytesMut) -> Result<(), ChunkIOError> {
+        if self.metadata.len() > MAX_METADATA_ENTRIES
+            || self
+                .metadata
+                .iter()
+                .any(|(key, value)| key.len() > MAX_KEY_LENGTH || value.len() > MAX_VALUE_LENGTH)
+        {
+            return Err(ChunkIOError::MetadataTooLarge);
+        }
+        dst.reserve(self.section_len());
+        dst.put_u16(self.kind);
+        dst.put_u8(self.metadata.len() as u8);
+        for (key, value) in &self.metadata {
+            dst.put_u8(key.len() as u8);
+            dst.put_slice(key.as_bytes());
+            dst.put_u16(value.len() as u16);
+            dst.put_slice(value);
+        }
+        Ok(())
+    }
+
+    /// Parses a chunk that was flagged as carrying kind and metadata.
+    pub(crate) fn decode(mut body: Bytes) -> Result<Chunk, ChunkIOError> {
+        if body.remaining() < 3 {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        let kind = body.get_u16();
+        let count = body.get_u8() as usize;
+        let mut metadata = Vec::with_capacity(count);
+        for _ in 0..count {
+            if !body.has_remaining() {
+                return Err(ChunkIOError::InvalidChunk);
+            }
+            let key_len = body.get_u8() as usize;
+            if body.remaining() < key_len + 2 {
+                return Err(ChunkIOError::InvalidChunk);
+            }
+            let key = String::from_utf8(body.split_to(key_len).to_vec())
+                .map_err(|_| ChunkIOError::InvalidChunk)?;
+            let value_len = body.get_u16() as usize;
+            if body.remaining() < value_len {
+                return Err(ChunkIOError::InvalidChunk);
+            }
+            metadata.push((key, body.split_to(value_len)));
+        }
+        let chunk = Chunk {
+            kind,
+            metadata,
+            payload: body,
+        };
+        // An empty section would not be sent, and offsets rely on the
+        // section being exactly what the sender counted.
+        if !chunk.has_section() {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        Ok(chunk)
+    }
+}
+
+impl From<Bytes> for Chunk {
+    fn from(payload: Bytes) -> Chunk {
+        Chunk::new(payload)
+    }
+}
+
+impl From<Vec<u8>> for Chunk {
+    fn from(payload: Vec<u8>) -> Chunk {
+        Chunk::new(payload)
+    }
+}
+
+impl From<Chunk> for Bytes {
+    fn from(chunk: Chunk) -> Bytes {
+        chunk.payload
+    }
+}
+
+impl From<Chunk> for Vec<u8> {
+    fn from(chunk: Chunk) -> Vec<u8> {
+        chunk.payload.into()
+    }
+}
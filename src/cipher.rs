@@ -0,0 +1,214 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+#[cfg(feature = "aead")]
+use aead::{AeadInPlace, KeyInit};
+#[cfg(feature = "aes-gcm")]
+use aes_gcm::Aes256Gcm;
+#[cfg(feature = "chacha20poly1305")]
+use chacha20poly1305::ChaCha20Poly1305;
+
+/// Length of the authentication tag appended to every sealed payload.
+pub const TAG_LEN: usize = 16;
+
+/// AEAD used to seal chunk payloads. Keys are 32 bytes for both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    #[cfg(feature = "chacha20poly1305")]
+    ChaCha20Poly1305,
+    #[cfg(feature = "aes-gcm")]
+    Aes256Gcm,
+}
+
+#[derive(Clone)]
+enum Aead {
+    #[cfg(feature = "chacha20poly1305")]
+    ChaCha20Poly1305(Box<ChaCha20Poly1305>),
+    #[cfg(feature = "aes-gcm")]
+    Aes256Gcm(Box<Aes256Gcm>),
+}
+
+impl Aead {
+    #[allow(unused_variables)]
+    fn new(cipher: Cipher, key: &[u8; 32]) -> Aead {
+        match cipher {
+            #[cfg(feature = "chacha20poly1305")]
+            Cipher::ChaCha20Poly1305 => {
+                Aead::ChaCha20Poly1305(Box::new(ChaCha20Poly1305::new(key.into())))
+            }
+            #[cfg(feature = "aes-gcm")]
+            Cipher::Aes256Gcm => Aead::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+        }
+    }
+}
+
+/// Payload encryption state: one key per direction, so both peers can number
+/// their nonces from the same offsets.
+#[derive(Clone)]
+pub(crate) struct Encryption {
+    cipher: Cipher,
+    send: Aead,
+    recv: Aead,
+    // Number of the next control frame sealed with `send`, shared by every
+    // copy so that halves, paths and later transports never repeat one.
+    control_sequence: Arc<AtomicU64>,
+    // Control frames accepted with `recv`, shared the same way, since paths
+    // may deliver them out of order.
+    control_replay: Arc<Mutex<ReplayWindow>>,
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption")
+            .field("cipher", &self.cipher)
+            .finish_non_exhaustive()
+    }
+}
+
+// Offsets never repeat within a direction, so they make unique nonces. Empty
+// chunks and the end of the stream share their offset with whatever follows,
+// so they are kept apart by the first byte. Two empty chunks at one offset
+// seal the same header and payload, which reveals nothing new. Control frames
+// have no offset and are numbered by a sequence of their own instead.
+const NONCE_DATA: u8 = 0;
+const NONCE_CONTROL: u8 = 1;
+const NONCE_EMPTY: u8 = 2;
+const NONCE_END: u8 = 3;
+
+#[allow(dead_code)]
+fn nonce(kind: u8, index: u64) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[0] = kind;
+    nonce[4..].copy_from_slice(&index.to_be_bytes());
+    nonce
+}
+
+fn data_nonce(index: u64, payload_len: usize) -> [u8; 12] {
+    match payload_len {
+        0 => nonce(NONCE_EMPTY, index),
+        _ => nonce(NONCE_DATA, index),
+    }
+}
+
+impl Encryption {
+    #[allow(unused_variables)]
+    pub(crate) fn new(cipher: Cipher, send_key: &[u8; 32], recv_key: &[u8; 32]) -> Encryption {
+        Encryption {
+            cipher,
+            send: Aead::new(cipher, send_key),
+            recv: Aead::new(cipher, recv_key),
+            control_sequence: Arc::default(),
+            control_replay: Arc::default(),
+        }
+    }
+
+    pub(crate) fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    /// Encrypts `payload` in place and returns the tag, binding `header` as
+    /// associated data.
+    pub(crate) fn seal(&self, index: u64, header: &[u8], payload: &mut [u8]) -> [u8; TAG_LEN] {
+        self.seal_with(data_nonce(index, payload.len()), header, payload)
+    }
+
+    /// Returns the tag of the end-of-stream frame at `index`.
+    pub(crate) fn seal_end(&self, index: u64, header: &[u8]) -> [u8; TAG_LEN] {
+        self.seal_with(nonce(NONCE_END, index), header, &mut [])
+    }
+
+    /// Takes the sequence number a control frame is sealed under.
+    pub(crate) fn next_control_sequence(&self) -> u64 {
+        self.control_sequence.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Returns the tag of a control frame, whose `header` ends with its
+    /// sequence number.
+    pub(crate) fn seal_control(&self, sequence: u64, header: &[u8]) -> [u8; TAG_LEN] {
+        self.seal_with(nonce(NONCE_CONTROL, sequence), header, &mut [])
+    }
+
+    #[allow(unused_variables)]
+    fn seal_with(&self, nonce: [u8; 12], header: &[u8], payload: &mut [u8]) -> [u8; TAG_LEN] {
+        // Sealing only fails for payloads over the cipher's limit of ~256 GiB.
+        match self.send {
+            #[cfg(feature = "chacha20poly1305")]
+            Aead::ChaCha20Poly1305(ref aead) => aead
+                .encrypt_in_place_detached(&nonce.into(), header, payload)
+                .expect("payload too large to seal")
+                .into(),
+            #[cfg(feature = "aes-gcm")]
+            Aead::Aes256Gcm(ref aead) => aead
+                .encrypt_in_place_detached(&nonce.into(), header, payload)
+                .expect("payload too large to seal")
+                .into(),
+        }
+    }
+
+    /// Decrypts a sealed payload in place, tag included, returning whether it
+    /// was authentic.
+    pub(crate) fn open(&self, index: u64, header: &[u8], sealed: &mut [u8]) -> bool {
+        let Some(split) = sealed.len().checked_sub(TAG_LEN) else {
+            return false;
+        };
+        let (payload, tag) = sealed.split_at_mut(split);
+        self.open_with(data_nonce(index, payload.len()), header, payload, tag)
+    }
+
+    /// Checks the tag of the end-of-stream frame at `index`.
+    pub(crate) fn open_end(&self, index: u64, header: &[u8], tag: &[u8]) -> bool {
+        self.open_with(nonce(NONCE_END, index), header, &mut [], tag)
+    }
+
+    /// Checks the tag of a control frame sealed under `sequence`, and that no
+    /// frame under it was accepted before.
+    pub(crate) fn open_control(&self, sequence: u64, header: &[u8], tag: &[u8]) -> bool {
+        self.open_with(nonce(NONCE_CONTROL, sequence), header, &mut [], tag)
+            && self.control_replay.lock().unwrap().accept(sequence)
+    }
+
+    #[allow(unused_variables)]
+    fn open_with(&self, nonce: [u8; 12], header: &[u8], payload: &mut [u8], tag: &[u8]) -> bool {
+        match self.recv {
+            #[cfg(feature = "chacha20poly1305")]
+            Aead::ChaCha20Poly1305(ref aead) => aead
+                .decrypt_in_place_detached(&nonce.into(), header, payload, tag.into())
+                .is_ok(),
+            #[cfg(feature = "aes-gcm")]
+            Aead::Aes256Gcm(ref aead) => aead
+                .decrypt_in_place_detached(&nonce.into(), header, payload, tag.into())
+                .is_ok(),
+        }
+    }
+}
+
+/// Sequence numbers seen so far: the highest, and a bitmap of the 64 below it.
+/// Anything older than that is refused.
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    next: u64,
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn accept(&mut self, sequence: u64) -> bool {
+        if sequence >= self.next {
+            let shift = sequence - self.next + 1;
+            self.seen = if shift >= 64 { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.next = sequence + 1;
+            return true;
+        }
+        let age = self.next - 1 - sequence;
+        if age >= 64 || self.seen & (1 << age) != 0 {
+            return false;
+        }
+        self.seen |= 1 << age;
+        true
+    }
+}
@@ -0,0 +1,139 @@
+use crate::error::ChunkIOError;
+
+// Payloads shorter than this are never worth compressing.
+const MIN_COMPRESS_LENGTH: usize = 64;
+
+/// Compression applied to each outgoing payload. A chunk is only sent
+/// compressed when that makes it smaller, and is flagged as such in its
+/// header. Both peers must use the same setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "zstd")]
+    Zstd,
+    /// LZ4 block format with the decompressed size prepended.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    #[cfg(feature = "deflate")]
+    Deflate,
+}
+
+impl Compression {
+    /// Every algorithm compiled in, in the order of their wire ids.
+    pub(crate) const SUPPORTED: &'static [Compression] = &[
+        Compression::None,
+        #[cfg(feature = "zstd")]
+        Compression::Zstd,
+        #[cfg(feature = "lz4")]
+        Compression::Lz4,
+        #[cfg(feature = "deflate")]
+        Compression::Deflate,
+    ];
+
+    /// Identifies the algorithm during negotiation.
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => 1,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => 2,
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => 3,
+        }
+    }
+
+    /// Returns the compressed payload, or `None` if it should go out as it is.
+    pub(crate) fn compress(&self, payload: &[u8]) -> Option<Vec<u8>> {
+        if payload.len() < MIN_COMPRESS_LENGTH {
+            return None;
+        }
+        let compressed: Vec<u8> = match self {
+            Compression::None => None,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                zstd::bulk::compress(payload, zstd::DEFAULT_COMPRESSION_LEVEL).ok()
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Some(lz4_flex::block::compress_prepend_size(payload)),
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => {
+                use std::io::Write;
+                let mut encoder = flate2::write::DeflateEncoder::new(
+                    Vec::with_capacity(payload.len()),
+                    flate2::Compression::default(),
+                );
+                encoder
+                    .write_all(payload)
+                    .and_then(|()| encoder.finish())
+                    .ok()
+            }
+        }?;
+        (compressed.len() < payload.len()).then_some(compressed)
+    }
+
+    /// Decompresses the payload of the chunk at `offset`, failing once it
+    /// would exceed `max` bytes.
+    #[allow(unused_variables)]
+    pub(crate) fn decompress(
+        &self,
+        offset: u64,
+        data: &[u8],
+        max: u64,
+    ) -> Result<Vec<u8>, ChunkIOError> {
+        let limit = usize::try_from(max).unwrap_or(usize::MAX);
+        match self {
+            // The peer flagged a chunk we have no algorithm for.
+            Compression::None => Err(ChunkIOError::InvalidChunk),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                use std::io::Read;
+                match zstd::zstd_safe::get_frame_content_size(data) {
+                    Ok(Some(size)) if size > max => {
+                        return Err(ChunkIOError::DecompressedTooLarge { offset, max })
+                    }
+                    Ok(Some(size)) => {
+                        return zstd::bulk::decompress(data, size as usize)
+                            .map_err(|_| ChunkIOError::Decompression { offset })
+                    }
+                    _ => {}
+                }
+                // Without a declared size, grow the output as it is produced.
+                let mut decompressed = Vec::new();
+                zstd::stream::read::Decoder::with_buffer(data)
+                    .map_err(|_| ChunkIOError::Decompression { offset })?
+                    .take(max.saturating_add(1))
+                    .read_to_end(&mut decompressed)
+                    .map_err(|_| ChunkIOError::Decompression { offset })?;
+                if decompressed.len() > limit {
+                    return Err(ChunkIOError::DecompressedTooLarge { offset, max });
+                }
+                Ok(decompressed)
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                let (size, data) = lz4_flex::block::uncompressed_size(data)
+                    .map_err(|_| ChunkIOError::Decompression { offset })?;
+                if size > limit {
+                    return Err(ChunkIOError::DecompressedTooLarge { offset, max });
+                }
+                lz4_flex::block::decompress(data, size)
+                    .map_err(|_| ChunkIOError::Decompression { offset })
+            }
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => {
+                use std::io::Read;
+                let mut decompressed = Vec::new();
+                flate2::read::DeflateDecoder::new(data)
+                    .take(max.saturating_add(1))
+                    .read_to_end(&mut decompressed)
+                    .map_err(|_| ChunkIOError::Decompression { offset })?;
+                if decompressed.len() > limit {
+                    return Err(ChunkIOError::DecompressedTooLarge { offset, max });
+                }
+                Ok(decompressed)
+            }
+        }
+    }
+}
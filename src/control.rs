@@ -0,0 +1,72 @@
+use tokio_util::bytes::BytesMut;
+
+use crate::{
+    error::ChunkIOError,
+    proto::{be_width, read_be},
+};
+
+const CONTROL_PING: u8 = 0x9;
+const CONTROL_PONG: u8 = 0xa;
+const CONTROL_CLOSE: u8 = 0xb;
+const CONTROL_RESET: u8 = 0xc;
+
+/// Frames carried in the header values a data chunk never uses: the high
+/// nibble selects the kind and the low nibble the width of its value.
+/// Control frames do not advance the byte offsets, and carry the checksum
+/// trailer like data chunks. When encrypted, the value is followed by a
+/// sequence number and a tag. Flow control is left to [`Mux`](crate::Mux),
+/// whose window updates name the stream they apply to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFrame {
+    Ping(u64),
+    Pong(u64),
+    /// Graceful close with an application-defined reason code. It is only
+    /// passed on to the application; the stream itself ends with the
+    /// end-of-stream frame, which proves that nothing was cut off.
+    Close(u64),
+    /// Abortive close with an application-defined reason code.
+    Reset(u64),
+}
+
+impl ControlFrame {
+    pub(crate) fn is_control(first: u8) -> bool {
+        (CONTROL_PING..=CONTROL_RESET).contains(&(first >> 4))
+    }
+
+    fn parts(&self) -> (u8, u64) {
+        match *self {
+            ControlFrame::Ping(v) => (CONTROL_PING, v),
+            ControlFrame::Pong(v) => (CONTROL_PONG, v),
+            ControlFrame::Close(v) => (CONTROL_CLOSE, v),
+            ControlFrame::Reset(v) => (CONTROL_RESET, v),
+        }
+    }
+
+    pub(crate) fn encode(&self, dst: &mut BytesMut) {
+        let (kind, value) = self.parts();
+        let width = be_width(value);
+        dst.extend_from_slice(&[(kind << 4) | width as u8]);
+        dst.extend_from_slice(&value.to_be_bytes()[8 - width..]);
+    }
+
+    /// Returns the frame and its encoded length, or `None` if `src` does not
+    /// hold all of it yet.
+    pub(crate) fn decode(src: &[u8]) -> Result<Option<(ControlFrame, usize)>, ChunkIOError> {
+        let width = (src[0] & 0xf) as usize;
+        if width > 8 {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        if src.len() < 1 + width {
+            return Ok(None);
+        }
+        let value = read_be(&src[1..1 + width]);
+        let frame = match src[0] >> 4 {
+            CONTROL_PING => ControlFrame::Ping(value),
+            CONTROL_PONG => ControlFrame::Pong(value),
+            CONTROL_CLOSE => ControlFrame::Close(value),
+            CONTROL_RESET => ControlFrame::Reset(value),
+            _ => return Err(ChunkIOError::InvalidChunk),
+        };
+        Ok(Some((frame, 1 + width)))
+    }
+}
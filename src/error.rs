@@ -2,10 +2,56 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ChunkIOError {
-    #[error("Chunk is empty")]
+    #[error("Chunk is malformed")]
     InvalidChunk,
     #[error("Chunk is out of order")]
     OutOfOrder,
+    #[error("Checksum mismatch in chunk at offset {offset}")]
+    ChecksumMismatch { offset: u64 },
+    #[error("Chunk at offset {offset} failed authentication")]
+    AuthenticationFailed { offset: u64 },
+    #[error("Chunk at offset {offset} could not be decompressed")]
+    Decompression { offset: u64 },
+    #[error("Chunk at offset {offset} decompresses to more than {max} bytes")]
+    DecompressedTooLarge { offset: u64, max: u64 },
+    #[error("Reassembly buffer is full")]
+    ReassemblyFull,
+    #[error("Chunk length {length} exceeds the maximum of {max}")]
+    ChunkTooLarge { length: u64, max: u64 },
+    #[error("Message length {length} exceeds the maximum of {max}")]
+    MessageTooLarge { length: u64, max: u64 },
+    #[error("Chunk metadata exceeds its size limits")]
+    MetadataTooLarge,
+    #[error("Transport is disconnected")]
+    Disconnected,
+    #[error("Peer resumed from offset {0} which is not buffered")]
+    InvalidResumeOffset(u64),
+    #[error("Peer sent an invalid hello")]
+    InvalidHello,
+    #[error("Peer only speaks protocol version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Peers disagree on the {0} capability")]
+    CapabilityMismatch(&'static str),
+    #[error("Stream was reset by the peer")]
+    Reset,
+    #[error("Stream is closed")]
+    StreamClosed,
+    #[error("Peer exceeded the flow control window")]
+    FlowControl,
+    #[error("Peer did not respond within the keepalive timeout")]
+    Timeout,
+    #[cfg(feature = "noise")]
+    #[error("Noise handshake failed: {0}")]
+    Handshake(#[from] snow::Error),
     #[error("IO error {0}")]
     Future(#[from] std::io::Error),
 }
+
+impl From<ChunkIOError> for std::io::Error {
+    fn from(e: ChunkIOError) -> std::io::Error {
+        match e {
+            ChunkIOError::Future(e) => e,
+            e => std::io::Error::other(e),
+        }
+    }
+}
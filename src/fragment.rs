@@ -0,0 +1,67 @@
+use tokio_util::bytes::{Bytes, BytesMut};
+
+use crate::{chunk::Chunk, error::ChunkIOError};
+
+pub const DEFAULT_MAX_MESSAGE_LENGTH: u64 = 64 * 1024 * 1024;
+
+/// A data chunk as received: a whole message or one fragment of it.
+#[derive(Debug, Clone)]
+pub(crate) struct Fragment {
+    pub(crate) body: Bytes,
+    // The message starts with its kind and metadata.
+    pub(crate) metadata: bool,
+    // Further fragments of the message follow.
+    pub(crate) more: bool,
+}
+
+/// Joins the fragments of a message as they arrive in order.
+#[derive(Debug, Clone)]
+pub(crate) struct Defragmenter {
+    partial: Option<(BytesMut, bool)>, // fragments so far, metadata flag
+    max_message_length: u64,
+}
+
+impl Defragmenter {
+    pub(crate) fn new(max_message_length: u64) -> Defragmenter {
+        Defragmenter {
+            partial: None,
+            max_message_length,
+        }
+    }
+
+    /// Whether a message was started but not finished.
+    pub(crate) fn is_partial(&self) -> bool {
+        self.partial.is_some()
+    }
+
+    /// Takes the next fragment and returns the message it completes, if any.
+    pub(crate) fn push(&mut self, fragment: Fragment) -> Result<Option<Chunk>, ChunkIOError> {
+        let (mut parts, metadata) = match self.partial.take() {
+            None if !fragment.more => return message(fragment.body, fragment.metadata).map(Some),
+            None => (BytesMut::new(), fragment.metadata),
+            // Only the first fragment may carry metadata.
+            Some(_) if fragment.metadata => return Err(ChunkIOError::InvalidChunk),
+            Some(partial) => partial,
+        };
+        let length = (parts.len() + fragment.body.len()) as u64;
+        if length > self.max_message_length {
+            return Err(ChunkIOError::MessageTooLarge {
+                length,
+                max: self.max_message_length,
+            });
+        }
+        parts.extend_from_slice(&fragment.body);
+        if fragment.more {
+            self.partial = Some((parts, metadata));
+            return Ok(None);
+        }
+        message(parts.freeze(), metadata).map(Some)
+    }
+}
+
+fn message(body: Bytes, metadata: bool) -> Result<Chunk, ChunkIOError> {
+    match metadata {
+        true => Chunk::decode(body),
+        false => Ok(Chunk::new(body)),
+    }
+}
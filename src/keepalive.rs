@@ -0,0 +1,64 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::ready;
+use tokio::time::{sleep, Instant, Sleep};
+
+use crate::{control::ControlFrame, error::ChunkIOError};
+
+/// Pings the peer once nothing has been received for `interval` and gives up
+/// if the silence lasts another `timeout`.
+pub(crate) struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+    last_activity: Instant,
+    ping_deadline: Option<Instant>,
+    next_ping: u64,
+}
+
+impl Keepalive {
+    pub(crate) fn new(interval: Duration, timeout: Duration) -> Keepalive {
+        Keepalive {
+            interval,
+            timeout,
+            sleep: Box::pin(sleep(interval)),
+            last_activity: Instant::now(),
+            ping_deadline: None,
+            next_ping: 0,
+        }
+    }
+
+    pub(crate) fn on_activity(&mut self) {
+        // The timer is only moved when it fires, to keep the hot path cheap.
+        self.last_activity = Instant::now();
+        self.ping_deadline = None;
+    }
+
+    /// Resolves with a ping to send, or with [`ChunkIOError::Timeout`] once a
+    /// ping went unanswered.
+    pub(crate) fn poll(&mut self, cx: &mut Context) -> Poll<Result<ControlFrame, ChunkIOError>> {
+        loop {
+            ready!(self.sleep.as_mut().poll(cx));
+            let now = Instant::now();
+            let deadline = self
+                .ping_deadline
+                .unwrap_or(self.last_activity + self.interval);
+            if deadline > now {
+                self.sleep.as_mut().reset(deadline);
+                continue;
+            }
+            if self.ping_deadline.is_some() {
+                return Poll::Ready(Err(ChunkIOError::Timeout));
+            }
+            self.ping_deadline = Some(now + self.timeout);
+            self.sleep.as_mut().reset(now + self.timeout);
+            self.next_ping = self.next_ping.wrapping_add(1);
+            return Poll::Ready(Ok(ControlFrame::Ping(self.next_ping)));
+        }
+    }
+}
@@ -1,157 +1,290 @@
+mod builder;
+mod byte_stream;
+mod checksum;
+mod chunk;
+mod cipher;
+mod compression;
+mod control;
 mod error;
+mod fragment;
+mod keepalive;
+mod multipath;
+mod mux;
+mod negotiate;
+#[cfg(feature = "noise")]
+mod noise;
+mod proto;
+mod read_state;
+mod reorder;
+mod resume;
+mod split;
+mod streaming;
+#[cfg(feature = "udp")]
+mod udp;
+mod write_buf;
 use std::{
+    future::poll_fn,
+    marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
 };
 
-use error::ChunkIOError;
-use futures::{Sink, SinkExt, Stream, StreamExt};
-use tokio::io::{AsyncRead, AsyncWrite};
+pub use builder::ChunkIOBuilder;
+pub use byte_stream::{ByteStream, DEFAULT_WRITE_CHUNK_SIZE};
+pub use checksum::Checksum;
+pub use chunk::Chunk;
+pub use cipher::{Cipher, TAG_LEN};
+pub use compression::Compression;
+pub use control::ControlFrame;
+pub use error::ChunkIOError;
+pub use fragment::DEFAULT_MAX_MESSAGE_LENGTH;
+use futures::{ready, Sink, Stream, StreamExt};
+use keepalive::Keepalive;
+pub use multipath::{
+    LowestLatency, MultipathChunkIO, PathInfo, RoundRobin, Scheduler, Weighted,
+    DEFAULT_MULTIPATH_REORDER_CAPACITY,
+};
+pub use mux::{Mux, MuxRole, MuxStream, DEFAULT_STREAM_WINDOW};
+pub use negotiate::{Capabilities, Negotiated, PROTOCOL_VERSION};
+#[cfg(feature = "noise")]
+pub use noise::{NoiseConfig, NoisePattern};
+pub use proto::{ChunkIOProto, Frame, DEFAULT_MAX_CHUNK_LENGTH};
+use read_state::{FrameSource, ReadState};
+pub use resume::{ResumableChunkIO, DEFAULT_RETRANSMIT_CAPACITY};
+pub use split::{ChunkReader, ChunkWriter, ReuniteError};
+pub use streaming::{ChunkHeader, Payload, StreamingReader};
+use tokio::io::{AsyncRead, AsyncWrite, Join};
 use tokio_util::{
-    bytes::{Buf, BytesMut},
-    codec::{Decoder, Encoder, Framed},
+    bytes::{Buf, Bytes, BytesMut},
+    codec::{Framed, FramedParts},
 };
+#[cfg(feature = "udp")]
+pub use udp::{UdpChunkIO, DEFAULT_DATAGRAM_SIZE, DEFAULT_UDP_WINDOW};
+use write_buf::WriteBuf;
 
-pub struct ChunkIO<T>(Framed<T, ChunkIOProto>);
+fn framed<T>(io: T, codec: ChunkIOProto, read_buf: BytesMut) -> Framed<T, ChunkIOProto> {
+    let mut parts = FramedParts::new::<Bytes>(io, codec);
+    parts.read_buf = read_buf;
+    Framed::from_parts(parts)
+}
+
+/// Chunked stream over `T`. Items are `Vec<u8>` by default; use `Bytes`
+/// (see [`ChunkIOBuilder::build_bytes`]) to receive chunks as slices of the
+/// read buffer and to send payloads without copying them, or [`Chunk`] (see
+/// [`ChunkIOBuilder::build_chunks`]) to also exchange a kind and metadata.
+///
+/// The stream ends when the peer closes its sending side; a transport that
+/// ends before then yields [`ChunkIOError::Disconnected`]. Without the
+/// end-of-stream frame (see [`ChunkIOBuilder::end_of_stream`]) the end of the
+/// transport is the end of the stream.
+pub struct ChunkIO<T, I = Vec<u8>> {
+    // Only read through; a `Framed` can be rebuilt around its read buffer
+    // when the stream is split and reunited.
+    inner: Framed<T, ChunkIOProto>,
+    write_buf: WriteBuf,
+    backpressure_boundary: usize,
+    read: ReadState,
+    local_closed: bool,
+    flush_control: bool,
+    _item: PhantomData<fn(I) -> I>,
+}
 
 impl<T> ChunkIO<T> {
     pub fn new(io: T) -> ChunkIO<T>
     where
         T: AsyncRead + AsyncWrite,
     {
-        ChunkIO(tokio_util::codec::Framed::new(io, Default::default()))
+        ChunkIOBuilder::new().build(io)
     }
 }
 
-impl<T> Stream for ChunkIO<T>
-where
-    T: AsyncRead + AsyncWrite + Unpin,
-{
-    type Item = Result<Vec<u8>, ChunkIOError>;
-
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        self.0.poll_next_unpin(cx)
+impl<R, W> ChunkIO<Join<R, W>> {
+    /// Speaks the protocol over a separate reader and writer, such as stdin
+    /// and stdout or a pair of pipes. Use [`tokio::io::join`] with
+    /// [`ChunkIOBuilder::build`] for other settings.
+    pub fn from_parts(reader: R, writer: W) -> ChunkIO<Join<R, W>>
+    where
+        R: AsyncRead,
+        W: AsyncWrite,
+    {
+        ChunkIO::new(tokio::io::join(reader, writer))
     }
 }
 
-impl<T> Sink<Vec<u8>> for ChunkIO<T>
-where
-    T: AsyncRead + AsyncWrite + Unpin,
-{
-    type Error = ChunkIOError;
-
-    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        self.0.poll_ready_unpin(cx)
+impl<T, I> ChunkIO<T, I> {
+    pub(crate) fn from_builder(io: T, builder: &ChunkIOBuilder) -> ChunkIO<T, I> {
+        ChunkIO {
+            inner: framed(
+                io,
+                builder.codec(),
+                BytesMut::with_capacity(builder.read_buffer_capacity),
+            ),
+            write_buf: WriteBuf::with_capacity(builder.write_buffer_capacity),
+            backpressure_boundary: builder.write_buffer_capacity,
+            read: ReadState::new(
+                builder.end_of_stream,
+                builder
+                    .keepalive
+                    .map(|(interval, timeout)| Keepalive::new(interval, timeout)),
+            ),
+            local_closed: false,
+            flush_control: false,
+            _item: PhantomData,
+        }
     }
 
-    fn start_send(mut self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), Self::Error> {
-        self.0.start_send_unpin(item)
+    pub fn codec(&self) -> &ChunkIOProto {
+        self.inner.codec()
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        self.0.poll_flush_unpin(cx)
+    pub(crate) fn codec_mut(&mut self) -> &mut ChunkIOProto {
+        self.inner.codec_mut()
     }
 
-    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
-        self.0.poll_close_unpin(cx)
+    /// Queues a control frame; it goes out with the next flush.
+    pub fn send_control(&mut self, frame: ControlFrame) {
+        self.inner
+            .codec()
+            .encode_control(frame, self.write_buf.tail_mut());
     }
-}
-
-#[derive(Default)]
-struct ChunkIOProto {
-    current_index: (u64, u64), // send index, receive index
-}
 
-impl Decoder for ChunkIOProto {
-    type Item = Vec<u8>;
-
-    type Error = ChunkIOError;
+    /// Returns the next control frame received from the peer. Control frames
+    /// are read while the data stream is polled; pings are answered
+    /// automatically.
+    pub fn poll_control(&mut self, cx: &mut Context) -> Poll<ControlFrame> {
+        self.read.poll_control(cx)
+    }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if src.len() < 2 {
-            return Ok(None);
-        }
-        let index_pointer = (src[0] >> 4) as u64;
-        let len_pointer = (src[0] & 0xf) as u64;
-        if index_pointer > 8 || len_pointer > 8 || len_pointer == 0 {
-            return Err(ChunkIOError::InvalidChunk);
-        }
-        if (src.len() as u64) < (len_pointer + index_pointer + 1) {
-            return Ok(None);
+    /// Sends the end-of-stream frame and flushes it, but keeps reading: the
+    /// peer's stream ends once it has received everything sent before. With
+    /// the end-of-stream frame turned off, the transport is shut down
+    /// instead, which only half-closes transports such as TCP.
+    pub fn poll_close_write(&mut self, cx: &mut Context) -> Poll<Result<(), ChunkIOError>>
+    where
+        T: AsyncWrite + Unpin,
+    {
+        if !self.local_closed {
+            self.inner.codec().encode_end(self.write_buf.tail_mut());
+            self.local_closed = true;
         }
-        let index = match index_pointer {
-            0 => 0_u64,
-            1 => u8::from_be_bytes([src[1]]) as u64,
-            2 => u16::from_be_bytes([src[1], src[2]]) as u64,
-            3..=4 => u32::from_be_bytes(
-                src[1..index_pointer as usize]
-                    .try_into()
-                    .or(Err(ChunkIOError::InvalidChunk))?,
-            ) as u64,
-            5..=8 => u64::from_be_bytes(
-                src[1..index_pointer as usize]
-                    .try_into()
-                    .or(Err(ChunkIOError::InvalidChunk))?,
-            ),
-            _ => return Err(ChunkIOError::InvalidChunk),
-        };
-        if self.current_index.1 != index {
-            return Err(ChunkIOError::OutOfOrder);
+        ready!(self.poll_write_buf(cx))?;
+        let end_of_stream = self.inner.codec().end_of_stream();
+        let io = Pin::new(self.inner.get_mut());
+        match end_of_stream {
+            true => io.poll_flush(cx).map_err(Into::into),
+            false => io.poll_shutdown(cx).map_err(Into::into),
         }
+    }
 
-        let length = match len_pointer {
-            1 => u8::from_be_bytes([src[1 + index_pointer as usize]]) as u64,
-            2 => u16::from_be_bytes([
-                src[1 + index_pointer as usize],
-                src[2 + index_pointer as usize],
-            ]) as u64,
-            3..=4 => u32::from_be_bytes(
-                src[1 + (index_pointer as usize)..((index_pointer + len_pointer) as usize)]
-                    .try_into()
-                    .or(Err(ChunkIOError::InvalidChunk))?,
-            ) as u64,
-            5..=8 => u64::from_be_bytes(
-                src[1 + index_pointer as usize..(index_pointer + len_pointer) as usize]
-                    .try_into()
-                    .or(Err(ChunkIOError::InvalidChunk))?,
-            ),
-            _ => {
-                return Err(ChunkIOError::InvalidChunk);
+    /// See [`ChunkIO::poll_close_write`].
+    pub async fn close_write(&mut self) -> Result<(), ChunkIOError>
+    where
+        T: AsyncWrite + Unpin,
+    {
+        poll_fn(|cx| self.poll_close_write(cx)).await
+    }
+
+    /// Writes out replies queued while reading without waiting on the
+    /// transport.
+    fn poll_flush_control(&mut self, cx: &mut Context) -> Result<(), ChunkIOError>
+    where
+        T: AsyncWrite + Unpin,
+    {
+        if self.flush_control {
+            if let Poll::Ready(result) = self.poll_write_buf(cx) {
+                result?;
+                if let Poll::Ready(result) = Pin::new(self.inner.get_mut()).poll_flush(cx) {
+                    self.flush_control = false;
+                    result?;
+                }
             }
-        };
-        if src.len() < (index_pointer + len_pointer + length + 1) as usize {
-            Ok(None)
-        } else {
-            src.advance((index_pointer + len_pointer + 1) as usize);
-            self.current_index.1 += length;
-            Ok(Some(src.split_to(length as usize).to_vec()))
         }
+        Ok(())
+    }
+
+    fn poll_write_buf(&mut self, cx: &mut Context) -> Poll<Result<(), ChunkIOError>>
+    where
+        T: AsyncWrite + Unpin,
+    {
+        self.write_buf.poll_write_to(self.inner.get_mut(), cx)
+    }
+}
+
+impl<T, I> FrameSource for ChunkIO<T, I>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn read_state(&mut self) -> &mut ReadState {
+        &mut self.read
+    }
+
+    fn poll_frame(&mut self, cx: &mut Context) -> Poll<Option<Result<Frame, ChunkIOError>>> {
+        self.inner.poll_next_unpin(cx)
+    }
+
+    fn send_reply(&mut self, frame: ControlFrame, cx: &mut Context) -> Result<(), ChunkIOError> {
+        self.send_control(frame);
+        self.flush_control = true;
+        self.poll_flush_control(cx)
     }
 }
 
-impl Encoder<Vec<u8>> for ChunkIOProto {
+impl<T, I> Stream for ChunkIO<T, I>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    I: From<Chunk>,
+{
+    type Item = Result<I, ChunkIOError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.poll_flush_control(cx)?;
+        self.poll_chunk(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map(I::from)))
+    }
+}
+
+impl<T, I> Sink<I> for ChunkIO<T, I>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    I: Into<Chunk>,
+{
     type Error = ChunkIOError;
 
-    fn encode(&mut self, item: Vec<u8>, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let index = self
-            .current_index
-            .0
-            .to_be_bytes()
-            .into_iter()
-            .skip_while(|x| *x == 0)
-            .collect::<Vec<u8>>();
-        let length = item
-            .len()
-            .to_be_bytes()
-            .into_iter()
-            .skip_while(|x| *x == 0)
-            .collect::<Vec<u8>>();
-        dst.extend_from_slice(&[((index.len() as u8) << 4) | (length.len() as u8)]);
-        dst.extend_from_slice(&index);
-        dst.extend_from_slice(&length);
-        dst.extend_from_slice(&item);
-        self.current_index.0 += item.len() as u64;
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if self.write_buf.remaining() >= self.backpressure_boundary {
+            ready!(self.poll_write_buf(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
 
+    fn start_send(mut self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let this = &mut *self;
+        if this.local_closed {
+            return Err(ChunkIOError::StreamClosed);
+        }
+        this.inner
+            .codec_mut()
+            .encode_chunk(item.into(), &mut this.write_buf)?;
         Ok(())
     }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_write_buf(cx))?;
+        Pin::new(self.inner.get_mut())
+            .poll_flush(cx)
+            .map_err(Into::into)
+    }
+
+    /// Sends the end-of-stream frame if turned on, then flushes and shuts
+    /// down the transport.
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_close_write(cx))?;
+        if !self.inner.codec().end_of_stream() {
+            // Already shut down.
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(self.inner.get_mut())
+            .poll_shutdown(cx)
+            .map_err(Into::into)
+    }
 }
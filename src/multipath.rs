@@ -0,0 +1,467 @@
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{ready, Sink, Stream, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::Instant,
+};
+use tokio_util::{
+    bytes::{Buf, BytesMut},
+    codec::{Decoder, FramedRead},
+};
+
+use crate::{
+    chunk::Chunk,
+    control::ControlFrame,
+    fragment::{Defragmenter, Fragment},
+    proto::{ChunkIOProto, RawFrame},
+    reorder::Reassembly,
+    write_buf::WriteBuf,
+    ChunkIOBuilder, ChunkIOError,
+};
+
+/// Least reorder capacity used when the builder sets none. It is raised to
+/// hold one chunk of the largest size from every path.
+pub const DEFAULT_MULTIPATH_REORDER_CAPACITY: usize = 4 * 1024 * 1024;
+
+// How often each path is probed for its round-trip time while it carries data.
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a [`Scheduler`] knows about one path.
+#[derive(Debug, Clone, Default)]
+pub struct PathInfo {
+    /// Latest round-trip time measured with a ping on this path.
+    pub rtt: Option<Duration>,
+    /// Payload bytes sent on this path so far.
+    pub sent: u64,
+    /// Whether the path can take another chunk without exceeding its write
+    /// buffer.
+    pub ready: bool,
+}
+
+/// Picks the path each outgoing chunk is sent on.
+pub trait Scheduler {
+    /// Returns the index of a ready path for a chunk of `len` bytes.
+    fn select(&mut self, paths: &[PathInfo], len: usize) -> Option<usize>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl Scheduler for RoundRobin {
+    fn select(&mut self, paths: &[PathInfo], _len: usize) -> Option<usize> {
+        let selected = (0..paths.len())
+            .map(|i| (self.next + i) % paths.len())
+            .find(|i| paths[*i].ready)?;
+        self.next = selected + 1;
+        Some(selected)
+    }
+}
+
+/// Sends on the ready path with the lowest measured round-trip time; paths
+/// that have not been measured yet are tried first.
+#[derive(Debug, Clone, Default)]
+pub struct LowestLatency;
+
+impl Scheduler for LowestLatency {
+    fn select(&mut self, paths: &[PathInfo], _len: usize) -> Option<usize> {
+        paths
+            .iter()
+            .enumerate()
+            .filter(|(_, path)| path.ready)
+            .min_by_key(|(_, path)| path.rtt.unwrap_or_default())
+            .map(|(i, _)| i)
+    }
+}
+
+/// Splits bytes across paths in proportion to their weights.
+#[derive(Debug, Clone)]
+pub struct Weighted {
+    weights: Vec<u32>,
+}
+
+impl Weighted {
+    pub fn new(weights: Vec<u32>) -> Weighted {
+        Weighted { weights }
+    }
+}
+
+impl Scheduler for Weighted {
+    fn select(&mut self, paths: &[PathInfo], _len: usize) -> Option<usize> {
+        let weight = |i: usize| self.weights.get(i).copied().unwrap_or(0) as u128;
+        paths
+            .iter()
+            .enumerate()
+            .filter(|(i, path)| path.ready && weight(*i) > 0)
+            .min_by(|(a, path_a), (b, path_b)| {
+                (path_a.sent as u128 * weight(*b)).cmp(&(path_b.sent as u128 * weight(*a)))
+            })
+            .map(|(i, _)| i)
+    }
+}
+
+struct PathCodec(ChunkIOProto);
+
+impl Decoder for PathCodec {
+    type Item = RawFrame;
+
+    type Error = ChunkIOError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.0.decode_raw(src, None)
+    }
+}
+
+struct Path<T> {
+    inner: FramedRead<T, PathCodec>,
+    write_buf: WriteBuf,
+    probe: Option<(u64, Instant)>,
+    last_probe: Option<Instant>,
+    flush_control: bool,
+    eof: bool,
+}
+
+impl<T> Path<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), ChunkIOError>> {
+        ready!(self.write_buf.poll_write_to(self.inner.get_mut(), cx))?;
+        ready!(Pin::new(self.inner.get_mut()).poll_flush(cx))?;
+        self.flush_control = false;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// One chunk stream striped across several transports.
+///
+/// Every chunk keeps its offset in the combined stream, so the receiving side
+/// merges whatever arrives on any path back into order. Losing a path loses
+/// the chunks in flight on it, so any path error fails the whole stream, and
+/// so does running out of reorder capacity.
+pub struct MultipathChunkIO<T, S, I = Vec<u8>> {
+    paths: Vec<Path<T>>,
+    infos: Vec<PathInfo>,
+    scheduler: S,
+    reassembly: Reassembly,
+    message: Defragmenter,
+    current_index: (u64, u64), // send index, receive index
+    backpressure_boundary: usize,
+    next_read: usize,
+    next_probe: u64,
+    // Set once the stream returned an error.
+    failed: bool,
+    // Final offset announced by the peer's end-of-stream frame.
+    end_index: Option<u64>,
+    local_closed: bool,
+    _item: PhantomData<fn(I) -> I>,
+}
+
+impl<T, S, I> MultipathChunkIO<T, S, I>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    S: Scheduler,
+{
+    pub fn new(transports: Vec<T>, builder: &ChunkIOBuilder, scheduler: S) -> Self {
+        let paths = transports
+            .into_iter()
+            .map(|io| Path {
+                inner: FramedRead::with_capacity(
+                    io,
+                    PathCodec(builder.codec()),
+                    builder.read_buffer_capacity,
+                ),
+                write_buf: WriteBuf::with_capacity(builder.write_buffer_capacity),
+                probe: None,
+                last_probe: None,
+                flush_control: false,
+                eof: false,
+            })
+            .collect::<Vec<_>>();
+        let reorder_capacity = builder.reorder_capacity.unwrap_or_else(|| {
+            let largest = builder
+                .max_chunk_length
+                .max(builder.max_decompressed_length);
+            let capacity = largest.saturating_mul(paths.len() as u64);
+            usize::try_from(capacity)
+                .unwrap_or(usize::MAX)
+                .max(DEFAULT_MULTIPATH_REORDER_CAPACITY)
+        });
+        MultipathChunkIO {
+            infos: vec![
+                PathInfo {
+                    ready: true,
+                    ..Default::default()
+                };
+                paths.len()
+            ],
+            paths,
+            scheduler,
+            reassembly: Reassembly::new(reorder_capacity),
+            message: Defragmenter::new(builder.max_message_length),
+            current_index: (0, 0),
+            backpressure_boundary: builder.write_buffer_capacity,
+            next_read: 0,
+            next_probe: 0,
+            failed: false,
+            end_index: None,
+            local_closed: false,
+            _item: PhantomData,
+        }
+    }
+
+    pub fn paths(&self) -> &[PathInfo] {
+        &self.infos
+    }
+
+    /// Returns the send and receive byte offsets of the combined stream.
+    pub fn current_index(&self) -> (u64, u64) {
+        self.current_index
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context) -> Poll<Option<Result<Chunk, ChunkIOError>>> {
+        for path in self.paths.iter_mut().filter(|path| path.flush_control) {
+            if let Poll::Ready(Err(e)) = path.poll_flush(cx) {
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+        loop {
+            if let Some(fragment) = self.reassembly.pop(self.current_index.1)? {
+                if let Some(chunk) = self.on_fragment(fragment)? {
+                    return Poll::Ready(Some(Ok(chunk)));
+                }
+                continue;
+            }
+            let eof = self.paths.iter().all(|path| path.eof);
+            if eof
+                && self
+                    .paths
+                    .first()
+                    .is_some_and(|path| path.inner.decoder().0.end_of_stream())
+            {
+                // Every transport ended before the peer finished the stream.
+                return Poll::Ready(Some(Err(ChunkIOError::Disconnected)));
+            }
+            if eof || self.end_index == Some(self.current_index.1) {
+                // The peer may not finish the stream in the middle of a message.
+                if self.message.is_partial() {
+                    return Poll::Ready(Some(Err(ChunkIOError::InvalidChunk)));
+                }
+                return Poll::Ready(None);
+            }
+            let mut progressed = false;
+            for n in 0..self.paths.len() {
+                let i = (self.next_read + n) % self.paths.len();
+                if self.paths[i].eof {
+                    continue;
+                }
+                match self.paths[i].inner.poll_next_unpin(cx) {
+                    Poll::Pending => continue,
+                    Poll::Ready(None) => self.paths[i].eof = true,
+                    Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                    Poll::Ready(Some(Ok(RawFrame::Control(frame)))) => {
+                        self.on_control(i, frame, cx)?
+                    }
+                    // Every path carries the end of the stream; it takes effect
+                    // once the data before it has arrived.
+                    Poll::Ready(Some(Ok(RawFrame::End { index }))) => {
+                        if index < self.current_index.1
+                            || self.end_index.is_some_and(|end| end != index)
+                        {
+                            return Poll::Ready(Some(Err(ChunkIOError::OutOfOrder)));
+                        }
+                        self.end_index = Some(index);
+                    }
+                    Poll::Ready(Some(Ok(RawFrame::Data { index, fragment }))) => {
+                        if let Some(fragment) =
+                            self.reassembly
+                                .insert(self.current_index.1, index, fragment)?
+                        {
+                            if let Some(chunk) = self.on_fragment(fragment)? {
+                                self.next_read = i + 1;
+                                return Poll::Ready(Some(Ok(chunk)));
+                            }
+                        }
+                    }
+                }
+                progressed = true;
+                self.next_read = i + 1;
+                break;
+            }
+            if !progressed {
+                return Poll::Pending;
+            }
+        }
+    }
+
+    fn on_control(
+        &mut self,
+        path: usize,
+        frame: ControlFrame,
+        cx: &mut Context,
+    ) -> Result<(), ChunkIOError> {
+        match frame {
+            ControlFrame::Ping(value) => {
+                let path = &mut self.paths[path];
+                path.inner
+                    .decoder()
+                    .0
+                    .encode_control(ControlFrame::Pong(value), path.write_buf.tail_mut());
+                path.flush_control = true;
+                if let Poll::Ready(Err(e)) = path.poll_flush(cx) {
+                    return Err(e);
+                }
+            }
+            ControlFrame::Pong(value) => {
+                if let Some((probe, sent_at)) = self.paths[path].probe {
+                    if probe == value {
+                        self.infos[path].rtt = Some(sent_at.elapsed());
+                        self.paths[path].probe = None;
+                    }
+                }
+            }
+            ControlFrame::Reset(_) => return Err(ChunkIOError::Reset),
+            ControlFrame::Close(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Takes the fragment at the receive offset and returns the message it
+    /// completes, if any.
+    fn on_fragment(&mut self, fragment: Fragment) -> Result<Option<Chunk>, ChunkIOError> {
+        self.current_index.1 += fragment.body.len() as u64;
+        self.message.push(fragment)
+    }
+
+    fn probe(&mut self, path: usize) {
+        let now = Instant::now();
+        let path = &mut self.paths[path];
+        if path.probe.is_some() || path.last_probe.is_some_and(|at| now - at < PROBE_INTERVAL) {
+            return;
+        }
+        self.next_probe = self.next_probe.wrapping_add(1);
+        path.inner.decoder().0.encode_control(
+            ControlFrame::Ping(self.next_probe),
+            path.write_buf.tail_mut(),
+        );
+        path.probe = Some((self.next_probe, now));
+        path.last_probe = Some(now);
+    }
+
+    fn poll_all<F>(&mut self, cx: &mut Context, mut f: F) -> Poll<Result<(), ChunkIOError>>
+    where
+        F: FnMut(&mut Path<T>, &mut Context) -> Poll<Result<(), ChunkIOError>>,
+    {
+        let mut pending = false;
+        for path in self.paths.iter_mut() {
+            match f(path, cx) {
+                Poll::Ready(result) => result?,
+                Poll::Pending => pending = true,
+            }
+        }
+        match pending {
+            true => Poll::Pending,
+            false => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+impl<T, S, I> Stream for MultipathChunkIO<T, S, I>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    S: Scheduler + Unpin,
+    I: From<Chunk>,
+{
+    type Item = Result<I, ChunkIOError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.failed {
+            return Poll::Ready(None);
+        }
+        let result = ready!(this.poll_receive(cx));
+        // The paths keep going after an error, but the stream behind it has a
+        // gap or an unfinished message.
+        this.failed = matches!(result, Some(Err(_)));
+        Poll::Ready(result.map(|result| result.map(I::from)))
+    }
+}
+
+impl<T, S, I> Sink<I> for MultipathChunkIO<T, S, I>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    S: Scheduler + Unpin,
+    I: Into<Chunk>,
+{
+    type Error = ChunkIOError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = &mut *self;
+        let mut ready = false;
+        for (path, info) in this.paths.iter_mut().zip(this.infos.iter_mut()) {
+            if path.write_buf.remaining() >= this.backpressure_boundary {
+                if let Poll::Ready(result) = path.write_buf.poll_write_to(path.inner.get_mut(), cx)
+                {
+                    result?;
+                }
+            }
+            info.ready = path.write_buf.remaining() < this.backpressure_boundary;
+            ready |= info.ready;
+        }
+        match ready {
+            true => Poll::Ready(Ok(())),
+            false => Poll::Pending,
+        }
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let this = &mut *self;
+        let chunk = item.into();
+        let length = chunk.stream_len() as u64;
+        let selected = this
+            .scheduler
+            .select(&this.infos, length as usize)
+            .ok_or(ChunkIOError::Disconnected)?;
+        let path = &mut this.paths[selected];
+        path.inner
+            .decoder()
+            .0
+            .encode_chunk_at(this.current_index.0, chunk, &mut path.write_buf)?;
+        this.current_index.0 += length;
+        this.infos[selected].sent += length;
+        this.probe(selected);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.poll_all(cx, |path, cx| path.poll_flush(cx))
+    }
+
+    /// Sends the end-of-stream frame on every path, then flushes and shuts
+    /// them down.
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if !self.local_closed {
+            let this = &mut *self;
+            for path in this.paths.iter_mut() {
+                path.inner
+                    .decoder()
+                    .0
+                    .encode_end_at(this.current_index.0, path.write_buf.tail_mut());
+            }
+            this.local_closed = true;
+        }
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.poll_all(cx, |path, cx| {
+            Pin::new(path.inner.get_mut())
+                .poll_shutdown(cx)
+                .map_err(Into::into)
+        })
+    }
+}
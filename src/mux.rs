@@ -0,0 +1,602 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::poll_fn,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+};
+
+use futures::{ready, Sink, SinkExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::bytes::{Buf, Bytes, BytesMut};
+
+use crate::{
+    proto::{be_width, read_be},
+    ChunkIO, ChunkIOError,
+};
+
+const FRAME_DATA: u8 = 0;
+const FRAME_OPEN: u8 = 1;
+const FRAME_FIN: u8 = 2;
+const FRAME_RESET: u8 = 3;
+const FRAME_WINDOW: u8 = 4;
+
+/// The credit each side starts a new stream with, before the acceptor's own
+/// window is known.
+pub const DEFAULT_STREAM_WINDOW: u32 = 256 * 1024;
+
+/// Decides which half of the stream id space this side allocates from, so
+/// both peers can open streams without colliding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MuxRole {
+    Client,
+    Server,
+}
+
+/// Wakes every task that waited on the shared transport, since the transport
+/// itself only remembers the most recent waker.
+#[derive(Default)]
+pub(crate) struct WakerSet(Mutex<Vec<Waker>>);
+
+impl WakerSet {
+    pub(crate) fn register(&self, waker: &Waker) {
+        let mut wakers = self.0.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+impl Wake for WakerSet {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = std::mem::take(&mut *self.0.lock().unwrap());
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+// An item larger than the send credit goes out in pieces as credit arrives,
+// so neither window ever goes below zero.
+struct StreamState {
+    current_index: (u64, u64), // send index, receive index
+    send_window: u64,
+    recv_window: u64,
+    consumed: u64,
+    inbound: VecDeque<Bytes>,
+    // The part of the last item that did not fit the send credit.
+    pending: Bytes,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+    local_closed: bool,
+    remote_closed: bool,
+    reset: bool,
+}
+
+impl StreamState {
+    fn new(send_window: u64, recv_window: u32) -> StreamState {
+        StreamState {
+            current_index: (0, 0),
+            send_window,
+            recv_window: recv_window as u64,
+            consumed: 0,
+            inbound: VecDeque::new(),
+            pending: Bytes::new(),
+            read_waker: None,
+            write_waker: None,
+            local_closed: false,
+            remote_closed: false,
+            reset: false,
+        }
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct MuxState<T> {
+    io: ChunkIO<T, Bytes>,
+    streams: HashMap<u32, StreamState>,
+    role: MuxRole,
+    window: u32,
+    next_id: u32,
+    accept_queue: VecDeque<u32>,
+    accept_waker: Option<Waker>,
+    needs_flush: bool,
+    failed: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<MuxState<T>>,
+    read_wakers: Arc<WakerSet>,
+    write_wakers: Arc<WakerSet>,
+}
+
+fn frame_header(kind: u8, id: u32, offset: Option<u64>, payload_len: usize) -> BytesMut {
+    let width = offset.map_or(0, be_width);
+    let mut frame = BytesMut::with_capacity(5 + width + payload_len);
+    frame.extend_from_slice(&[(kind << 4) | width as u8]);
+    frame.extend_from_slice(&id.to_be_bytes());
+    if let Some(offset) = offset {
+        frame.extend_from_slice(&offset.to_be_bytes()[8 - width..]);
+    }
+    frame
+}
+
+impl<T> MuxState<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn fail(&mut self) {
+        self.failed = true;
+        self.streams.values_mut().for_each(StreamState::wake);
+        if let Some(waker) = self.accept_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn poll_frame(
+        &mut self,
+        cx: &mut Context,
+        wakers: &Arc<WakerSet>,
+        write_wakers: &Arc<WakerSet>,
+    ) -> Poll<Result<(), ChunkIOError>> {
+        if self.failed {
+            return Poll::Ready(Err(ChunkIOError::Disconnected));
+        }
+        self.poll_flush_control(cx, write_wakers)?;
+        wakers.register(cx.waker());
+        let waker = Waker::from(wakers.clone());
+        let result = match ready!(self.io.poll_next_unpin(&mut Context::from_waker(&waker))) {
+            Some(Ok(frame)) => self.dispatch(frame),
+            Some(Err(e)) => Err(e),
+            None => Err(ChunkIOError::Disconnected),
+        };
+        if result.is_err() {
+            self.fail();
+        }
+        Poll::Ready(result)
+    }
+
+    /// Pushes out window updates queued while reading, without waiting on
+    /// the transport.
+    fn poll_flush_control(
+        &mut self,
+        cx: &mut Context,
+        write_wakers: &Arc<WakerSet>,
+    ) -> Result<(), ChunkIOError> {
+        if !self.needs_flush {
+            return Ok(());
+        }
+        match self.poll_write(cx, write_wakers, |io, cx| io.poll_flush(cx)) {
+            Poll::Ready(Ok(())) => self.needs_flush = false,
+            Poll::Ready(Err(e)) => return Err(e),
+            Poll::Pending => {}
+        }
+        Ok(())
+    }
+
+    fn send_window_update(&mut self, id: u32, increment: u64) -> Result<(), ChunkIOError> {
+        let frame = frame_header(FRAME_WINDOW, id, Some(increment), 0);
+        self.io.start_send_unpin(frame.freeze())?;
+        self.needs_flush = true;
+        Ok(())
+    }
+
+    fn dispatch(&mut self, mut frame: Bytes) -> Result<(), ChunkIOError> {
+        if frame.len() < 5 {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        let kind = frame[0] >> 4;
+        let width = (frame[0] & 0xf) as usize;
+        if width > 8 || frame.len() < 5 + width {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        frame.advance(1);
+        let id = frame.get_u32();
+        let offset = read_be(&frame[..width]);
+        frame.advance(width);
+
+        if kind == FRAME_OPEN {
+            let remote_role = (id % 2 == 1) == (self.role == MuxRole::Server);
+            if !remote_role || self.streams.contains_key(&id) {
+                return Err(ChunkIOError::InvalidChunk);
+            }
+            // The opener announces its receive window in the offset field and
+            // has already been granted the default one.
+            let mut stream = StreamState::new(offset, DEFAULT_STREAM_WINDOW);
+            if let Some(increment) = self.window.checked_sub(DEFAULT_STREAM_WINDOW) {
+                if increment > 0 {
+                    stream.recv_window += increment as u64;
+                    self.send_window_update(id, increment as u64)?;
+                }
+            }
+            self.streams.insert(id, stream);
+            self.accept_queue.push_back(id);
+            if let Some(waker) = self.accept_waker.take() {
+                waker.wake();
+            }
+            return Ok(());
+        }
+        // Frames for streams this side already dropped are discarded.
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return Ok(());
+        };
+        match kind {
+            FRAME_DATA | FRAME_FIN => {
+                if stream.remote_closed {
+                    return Err(ChunkIOError::InvalidChunk);
+                }
+                if stream.current_index.1 != offset {
+                    return Err(ChunkIOError::OutOfOrder);
+                }
+                if kind == FRAME_FIN {
+                    stream.remote_closed = true;
+                } else {
+                    if frame.len() as u64 > stream.recv_window {
+                        return Err(ChunkIOError::FlowControl);
+                    }
+                    stream.recv_window -= frame.len() as u64;
+                    stream.current_index.1 += frame.len() as u64;
+                    stream.inbound.push_back(frame);
+                }
+            }
+            FRAME_RESET => stream.reset = true,
+            FRAME_WINDOW => stream.send_window = stream.send_window.saturating_add(offset),
+            _ => return Err(ChunkIOError::InvalidChunk),
+        }
+        stream.wake();
+        Ok(())
+    }
+
+    /// Sends as much of the stream's pending data as its credit allows.
+    fn send_pending(&mut self, id: u32) -> Result<(), ChunkIOError> {
+        let stream = self.streams.get_mut(&id).unwrap();
+        let len = stream
+            .pending
+            .len()
+            .min(usize::try_from(stream.send_window).unwrap_or(usize::MAX));
+        if len == 0 {
+            return Ok(());
+        }
+        let mut frame = frame_header(FRAME_DATA, id, Some(stream.current_index.0), len);
+        frame.extend_from_slice(&stream.pending.split_to(len));
+        stream.current_index.0 += len as u64;
+        stream.send_window -= len as u64;
+        self.io.start_send_unpin(frame.freeze())
+    }
+
+    /// Sends the stream's pending data as credit arrives. With `credit`, also
+    /// waits until there is credit left for another item.
+    fn poll_send_pending(
+        &mut self,
+        id: u32,
+        cx: &mut Context,
+        read_wakers: &Arc<WakerSet>,
+        write_wakers: &Arc<WakerSet>,
+        credit: bool,
+    ) -> Poll<Result<(), ChunkIOError>> {
+        loop {
+            let stream = self.streams.get_mut(&id).unwrap();
+            let has_credit = stream.send_window > 0;
+            if stream.pending.is_empty() && (has_credit || !credit) {
+                return Poll::Ready(Ok(()));
+            }
+            if stream.reset {
+                return Poll::Ready(Err(ChunkIOError::Reset));
+            }
+            if has_credit {
+                ready!(self.poll_write(cx, write_wakers, |io, cx| io.poll_ready(cx)))?;
+                self.send_pending(id)?;
+                continue;
+            }
+            // Out of credit: window updates only arrive by reading, and the
+            // peer only sends them for data it has received.
+            stream.write_waker = Some(cx.waker().clone());
+            ready!(self.poll_write(cx, write_wakers, |io, cx| io.poll_flush(cx)))?;
+            ready!(self.poll_frame(cx, read_wakers, write_wakers))?;
+        }
+    }
+
+    fn poll_write<F>(
+        &mut self,
+        cx: &mut Context,
+        wakers: &Arc<WakerSet>,
+        f: F,
+    ) -> Poll<Result<(), ChunkIOError>>
+    where
+        F: FnOnce(Pin<&mut ChunkIO<T, Bytes>>, &mut Context) -> Poll<Result<(), ChunkIOError>>,
+    {
+        if self.failed {
+            return Poll::Ready(Err(ChunkIOError::Disconnected));
+        }
+        wakers.register(cx.waker());
+        let waker = Waker::from(wakers.clone());
+        let result = ready!(f(Pin::new(&mut self.io), &mut Context::from_waker(&waker)));
+        if result.is_err() {
+            self.fail();
+        }
+        Poll::Ready(result)
+    }
+}
+
+/// Opens and accepts logical streams multiplexed over one [`ChunkIO`].
+///
+/// There is no background task: whichever handle is polled reads the
+/// transport and routes frames to the stream they belong to.
+pub struct Mux<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for Mux<T> {
+    fn clone(&self) -> Self {
+        Mux {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Mux<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(io: ChunkIO<T, Bytes>, role: MuxRole) -> Mux<T> {
+        Mux::with_window(io, role, DEFAULT_STREAM_WINDOW)
+    }
+
+    /// Uses `window` as the number of payload bytes each stream lets the
+    /// peer send ahead of what has been read from it. Streams the peer opens
+    /// start out with [`DEFAULT_STREAM_WINDOW`], so a smaller window only
+    /// takes effect once that credit is used up.
+    pub fn with_window(io: ChunkIO<T, Bytes>, role: MuxRole, window: u32) -> Mux<T> {
+        Mux {
+            shared: Arc::new(Shared {
+                state: Mutex::new(MuxState {
+                    io,
+                    streams: HashMap::new(),
+                    role,
+                    window,
+                    next_id: match role {
+                        MuxRole::Client => 1,
+                        MuxRole::Server => 2,
+                    },
+                    accept_queue: VecDeque::new(),
+                    accept_waker: None,
+                    needs_flush: false,
+                    failed: false,
+                }),
+                read_wakers: Default::default(),
+                write_wakers: Default::default(),
+            }),
+        }
+    }
+
+    /// Opens a new stream. The peer learns about it once the stream is
+    /// flushed.
+    pub fn open_stream(&self) -> Result<MuxStream<T>, ChunkIOError> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.failed {
+            return Err(ChunkIOError::Disconnected);
+        }
+        let id = state.next_id;
+        state.next_id = state
+            .next_id
+            .checked_add(2)
+            .ok_or(ChunkIOError::StreamClosed)?;
+        let window = state.window;
+        state
+            .io
+            .start_send_unpin(frame_header(FRAME_OPEN, id, Some(window as u64), 0).freeze())?;
+        state
+            .streams
+            .insert(id, StreamState::new(DEFAULT_STREAM_WINDOW as u64, window));
+        Ok(MuxStream {
+            id,
+            shared: self.shared.clone(),
+        })
+    }
+
+    pub fn poll_accept(
+        &self,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<MuxStream<T>, ChunkIOError>>> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(id) = state.accept_queue.pop_front() {
+                return Poll::Ready(Some(Ok(MuxStream {
+                    id,
+                    shared: self.shared.clone(),
+                })));
+            }
+            if state.failed {
+                return Poll::Ready(None);
+            }
+            state.accept_waker = Some(cx.waker().clone());
+            match ready!(state.poll_frame(cx, &self.shared.read_wakers, &self.shared.write_wakers))
+            {
+                Ok(()) => continue,
+                Err(ChunkIOError::Disconnected) => return Poll::Ready(None),
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+    }
+
+    /// Waits for the peer to open a stream; `None` once the connection is
+    /// gone.
+    pub async fn accept_stream(&self) -> Option<Result<MuxStream<T>, ChunkIOError>> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+}
+
+/// One logical stream of a [`Mux`].
+///
+/// Items larger than the credit the peer has granted are sent in pieces, and
+/// may reach it as several items.
+pub struct MuxStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    id: u32,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> MuxStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the send and receive byte offsets of this stream.
+    pub fn current_index(&self) -> (u64, u64) {
+        let state = self.shared.state.lock().unwrap();
+        state.streams[&self.id].current_index
+    }
+}
+
+impl<T> Stream for MuxStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<Vec<u8>, ChunkIOError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut state = self.shared.state.lock().unwrap();
+        let window = state.window as u64;
+        loop {
+            let stream = state.streams.get_mut(&self.id).unwrap();
+            if let Some(payload) = stream.inbound.pop_front() {
+                stream.consumed += payload.len() as u64;
+                if stream.consumed >= window / 2 && !stream.remote_closed {
+                    stream.consumed = 0;
+                    // Tops the credit back up to the window; a stream that
+                    // started with more than its window grants less.
+                    let buffered = stream.inbound.iter().map(Bytes::len).sum::<usize>();
+                    let increment = window.saturating_sub(stream.recv_window + buffered as u64);
+                    if increment > 0 {
+                        stream.recv_window += increment;
+                        state.send_window_update(self.id, increment)?;
+                        state.poll_flush_control(cx, &self.shared.write_wakers)?;
+                    }
+                }
+                return Poll::Ready(Some(Ok(payload.into())));
+            }
+            if stream.remote_closed {
+                return Poll::Ready(None);
+            }
+            if stream.reset {
+                return Poll::Ready(Some(Err(ChunkIOError::Reset)));
+            }
+            stream.read_waker = Some(cx.waker().clone());
+            if let Err(e) =
+                ready!(state.poll_frame(cx, &self.shared.read_wakers, &self.shared.write_wakers))
+            {
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+    }
+}
+
+impl<T> Sink<Vec<u8>> for MuxStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    type Error = ChunkIOError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let mut state = self.shared.state.lock().unwrap();
+        let stream = &state.streams[&self.id];
+        if stream.reset {
+            return Poll::Ready(Err(ChunkIOError::Reset));
+        }
+        if stream.local_closed {
+            return Poll::Ready(Err(ChunkIOError::StreamClosed));
+        }
+        let shared = &self.shared;
+        ready!(state.poll_send_pending(
+            self.id,
+            cx,
+            &shared.read_wakers,
+            &shared.write_wakers,
+            true
+        ))?;
+        state.poll_write(cx, &self.shared.write_wakers, |io, cx| io.poll_ready(cx))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), Self::Error> {
+        let mut state = self.shared.state.lock().unwrap();
+        let stream = state.streams.get_mut(&self.id).unwrap();
+        if stream.local_closed {
+            return Err(ChunkIOError::StreamClosed);
+        }
+        if item.is_empty() {
+            // Empty items take no credit.
+            let frame = frame_header(FRAME_DATA, self.id, Some(stream.current_index.0), 0);
+            return state.io.start_send_unpin(frame.freeze());
+        }
+        stream.pending = item.into();
+        state.send_pending(self.id)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let mut state = self.shared.state.lock().unwrap();
+        let shared = &self.shared;
+        ready!(state.poll_send_pending(
+            self.id,
+            cx,
+            &shared.read_wakers,
+            &shared.write_wakers,
+            false
+        ))?;
+        state.poll_write(cx, &self.shared.write_wakers, |io, cx| io.poll_flush(cx))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.streams[&self.id].local_closed {
+            let shared = &self.shared;
+            ready!(state.poll_send_pending(
+                self.id,
+                cx,
+                &shared.read_wakers,
+                &shared.write_wakers,
+                false
+            ))?;
+            ready!(state.poll_write(cx, &self.shared.write_wakers, |io, cx| io.poll_ready(cx)))?;
+            let stream = state.streams.get_mut(&self.id).unwrap();
+            stream.local_closed = true;
+            let fin = frame_header(FRAME_FIN, self.id, Some(stream.current_index.0), 0);
+            state.io.start_send_unpin(fin.freeze())?;
+        }
+        state.poll_write(cx, &self.shared.write_wakers, |io, cx| io.poll_flush(cx))
+    }
+}
+
+impl<T> Drop for MuxStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn drop(&mut self) {
+        let Ok(mut state) = self.shared.state.lock() else {
+            return;
+        };
+        let Some(stream) = state.streams.remove(&self.id) else {
+            return;
+        };
+        if !(state.failed || stream.reset || stream.local_closed && stream.remote_closed) {
+            // Queued only; it goes out with the next flush of any stream.
+            let _ = state
+                .io
+                .start_send_unpin(frame_header(FRAME_RESET, self.id, None, 0).freeze());
+        }
+    }
+}
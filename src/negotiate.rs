@@ -0,0 +1,218 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    checksum::Checksum, compression::Compression, mux::MuxRole, ChunkIO, ChunkIOBuilder,
+    ChunkIOError, Mux,
+};
+
+/// Version 2 added the end-of-stream frame.
+pub const PROTOCOL_VERSION: u8 = 2;
+// Oldest version this side still speaks.
+const MIN_PROTOCOL_VERSION: u8 = 1;
+
+const MAGIC: &[u8; 4] = b"CKIO";
+// Magic, highest and lowest version, capabilities, supported and preferred
+// checksums, supported and preferred compression, extension length.
+const HELLO_LEN: usize = 4 + 2 + 1 + 2 + 2 + 2;
+
+// The other bits are reserved and ignored.
+const CAP_CONTROL_FRAMES: u8 = 0x1;
+const CAP_MUX: u8 = 0x2;
+const CAP_ENCRYPTION: u8 = 0x4;
+
+/// Optional features advertised during negotiation that do not change how
+/// chunks are framed. `control_frames` ends up enabled only if both peers
+/// offer it. The others decide what runs over the stream once negotiation is
+/// done, so both peers must offer the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The peer handles control frames; without it, keepalive pings are
+    /// turned off.
+    pub control_frames: bool,
+    /// Streams are multiplexed; offered by [`Mux::negotiate`].
+    pub mux: bool,
+    /// Peers run a Noise handshake; offered by `ChunkIO::negotiate_handshake`.
+    pub encryption: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            control_frames: true,
+            mux: false,
+            encryption: false,
+        }
+    }
+}
+
+impl Capabilities {
+    fn bits(&self) -> u8 {
+        (self.control_frames as u8 * CAP_CONTROL_FRAMES)
+            | (self.mux as u8 * CAP_MUX)
+            | (self.encryption as u8 * CAP_ENCRYPTION)
+    }
+
+    fn from_bits(bits: u8) -> Capabilities {
+        Capabilities {
+            control_frames: bits & CAP_CONTROL_FRAMES != 0,
+            mux: bits & CAP_MUX != 0,
+            encryption: bits & CAP_ENCRYPTION != 0,
+        }
+    }
+}
+
+/// Session settings both peers agreed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    /// Highest protocol version both peers speak.
+    pub version: u8,
+    pub checksum: Checksum,
+    pub compression: Compression,
+    pub capabilities: Capabilities,
+}
+
+fn supported_mask(ids: impl Iterator<Item = u8>) -> u8 {
+    ids.fold(0, |mask, id| mask | (1 << id))
+}
+
+/// Picks whichever of the two preferred algorithms both sides support,
+/// breaking ties by id.
+fn select<A: Copy + Default>(supported: &[A], id: fn(&A) -> u8, preferred: [u8; 2], peer: u8) -> A {
+    supported
+        .iter()
+        .copied()
+        .filter(|a| id(a) != 0 && preferred.contains(&id(a)) && peer & (1 << id(a)) != 0)
+        .max_by_key(id)
+        .unwrap_or_default()
+}
+
+impl Negotiated {
+    /// Exchanges hellos over `io` before any chunk is sent. The builder's
+    /// checksum and compression are the preferred ones; either side's
+    /// preference is used when the other supports it.
+    pub async fn exchange<T>(
+        io: &mut T,
+        builder: &ChunkIOBuilder,
+    ) -> Result<Negotiated, ChunkIOError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut hello = [0u8; HELLO_LEN];
+        hello[..4].copy_from_slice(MAGIC);
+        hello[4] = PROTOCOL_VERSION;
+        hello[5] = MIN_PROTOCOL_VERSION;
+        hello[6] = builder.capabilities.bits();
+        hello[7] = supported_mask(Checksum::SUPPORTED.iter().map(Checksum::id));
+        hello[8] = builder.checksum.id();
+        hello[9] = supported_mask(Compression::SUPPORTED.iter().map(Compression::id));
+        hello[10] = builder.compression.id();
+        // No extensions yet; later versions append theirs and older peers
+        // skip them.
+        io.write_all(&hello).await?;
+        io.flush().await?;
+
+        io.read_exact(&mut hello).await?;
+        if &hello[..4] != MAGIC {
+            return Err(ChunkIOError::InvalidHello);
+        }
+        let extension_len = u16::from_be_bytes([hello[11], hello[12]]);
+        tokio::io::copy(
+            &mut (&mut *io).take(extension_len as u64),
+            &mut tokio::io::sink(),
+        )
+        .await?;
+
+        let version = PROTOCOL_VERSION.min(hello[4]);
+        if version < MIN_PROTOCOL_VERSION.max(hello[5]) {
+            return Err(ChunkIOError::UnsupportedVersion(hello[4]));
+        }
+        let offered = builder.capabilities.bits();
+        for (bit, name) in [(CAP_MUX, "mux"), (CAP_ENCRYPTION, "encryption")] {
+            if (offered ^ hello[6]) & bit != 0 {
+                return Err(ChunkIOError::CapabilityMismatch(name));
+            }
+        }
+        Ok(Negotiated {
+            version,
+            checksum: select(
+                Checksum::SUPPORTED,
+                Checksum::id,
+                [builder.checksum.id(), hello[8]],
+                hello[7],
+            ),
+            compression: select(
+                Compression::SUPPORTED,
+                Compression::id,
+                [builder.compression.id(), hello[10]],
+                hello[9],
+            ),
+            capabilities: Capabilities::from_bits(offered & hello[6]),
+        })
+    }
+
+    /// Returns `builder` with the negotiated framing settings, without
+    /// keepalive unless both peers handle control frames, and without the
+    /// end-of-stream frame if the peer predates it.
+    pub fn apply(&self, builder: ChunkIOBuilder) -> ChunkIOBuilder {
+        let mut builder = builder
+            .checksum(self.checksum)
+            .compression(self.compression);
+        if !self.capabilities.control_frames {
+            builder.keepalive = None;
+        }
+        if self.version < 2 {
+            builder.end_of_stream = false;
+        }
+        builder
+    }
+}
+
+/// Exchanges hellos offering `mux` and `encryption` as given, whatever the
+/// builder says.
+pub(crate) async fn exchange_offering<T>(
+    io: &mut T,
+    builder: &ChunkIOBuilder,
+    mux: bool,
+    encryption: bool,
+) -> Result<Negotiated, ChunkIOError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut builder = builder.clone();
+    builder.capabilities.mux = mux;
+    builder.capabilities.encryption = encryption;
+    Negotiated::exchange(io, &builder).await
+}
+
+impl<T> ChunkIO<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Negotiates the session settings with the peer, see
+    /// [`Negotiated::exchange`], and starts the stream with them. Neither mux
+    /// nor encryption is offered.
+    pub async fn negotiate(
+        mut io: T,
+        builder: &ChunkIOBuilder,
+    ) -> Result<(ChunkIO<T>, Negotiated), ChunkIOError> {
+        let negotiated = exchange_offering(&mut io, builder, false, false).await?;
+        Ok((negotiated.apply(builder.clone()).build(io), negotiated))
+    }
+}
+
+impl<T> Mux<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Negotiates like [`ChunkIO::negotiate`], offering mux, and multiplexes
+    /// the stream.
+    pub async fn negotiate(
+        mut io: T,
+        builder: &ChunkIOBuilder,
+        role: MuxRole,
+    ) -> Result<(Mux<T>, Negotiated), ChunkIOError> {
+        let negotiated = exchange_offering(&mut io, builder, true, false).await?;
+        let io = negotiated.apply(builder.clone()).build_bytes(io);
+        Ok((Mux::new(io, role), negotiated))
+    }
+}
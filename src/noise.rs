@@ -0,0 +1,187 @@
+use std::fmt;
+
+use futures::{SinkExt, StreamExt};
+use snow::params::NoiseParams;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    cipher::{Cipher, Encryption},
+    negotiate::{exchange_offering, Negotiated},
+    ChunkIO, ChunkIOBuilder, ChunkIOError,
+};
+
+// Mixed into the handshake hash so the keys are bound to this protocol.
+const PROLOGUE: &[u8] = b"chunkio";
+const MAX_MESSAGE_LEN: usize = 65535;
+
+/// Noise handshake pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoisePattern {
+    /// Both static keys are exchanged during the handshake.
+    Xx,
+    /// The initiator already knows the responder's static key; set it with
+    /// [`NoiseConfig::remote_public_key`].
+    Ik,
+}
+
+/// Settings for [`ChunkIO::handshake`].
+#[derive(Clone)]
+pub struct NoiseConfig {
+    initiator: bool,
+    private_key: [u8; 32],
+    remote_public_key: Option<[u8; 32]>,
+    pattern: NoisePattern,
+    cipher: Cipher,
+    builder: ChunkIOBuilder,
+}
+
+impl NoiseConfig {
+    pub fn initiator(private_key: [u8; 32]) -> NoiseConfig {
+        NoiseConfig::new(true, private_key)
+    }
+
+    pub fn responder(private_key: [u8; 32]) -> NoiseConfig {
+        NoiseConfig::new(false, private_key)
+    }
+
+    fn new(initiator: bool, private_key: [u8; 32]) -> NoiseConfig {
+        NoiseConfig {
+            initiator,
+            private_key,
+            remote_public_key: None,
+            pattern: NoisePattern::Xx,
+            cipher: Cipher::ChaCha20Poly1305,
+            builder: ChunkIOBuilder::new(),
+        }
+    }
+
+    /// Generates a Curve25519 static key pair, returned as (private, public).
+    pub fn generate_keypair() -> Result<([u8; 32], [u8; 32]), ChunkIOError> {
+        let params = noise_params(NoisePattern::Xx, Cipher::ChaCha20Poly1305);
+        let keypair = snow::Builder::new(params).generate_keypair()?;
+        let mut private_key = [0; 32];
+        let mut public_key = [0; 32];
+        private_key.copy_from_slice(&keypair.private);
+        public_key.copy_from_slice(&keypair.public);
+        Ok((private_key, public_key))
+    }
+
+    pub fn pattern(mut self, pattern: NoisePattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    pub fn remote_public_key(mut self, remote_public_key: [u8; 32]) -> Self {
+        self.remote_public_key = Some(remote_public_key);
+        self
+    }
+
+    /// Cipher the session keys are used with; also the Noise cipher of the
+    /// handshake itself.
+    pub fn cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Settings for the resulting stream. Its encryption is ignored during the
+    /// handshake and replaced by the negotiated keys afterwards.
+    pub fn builder(mut self, builder: ChunkIOBuilder) -> Self {
+        self.builder = builder;
+        self
+    }
+}
+
+impl fmt::Debug for NoiseConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoiseConfig")
+            .field("initiator", &self.initiator)
+            .field("remote_public_key", &self.remote_public_key)
+            .field("pattern", &self.pattern)
+            .field("cipher", &self.cipher)
+            .field("builder", &self.builder)
+            .finish_non_exhaustive()
+    }
+}
+
+fn noise_params(pattern: NoisePattern, cipher: Cipher) -> NoiseParams {
+    let pattern = match pattern {
+        NoisePattern::Xx => "XX",
+        NoisePattern::Ik => "IK",
+    };
+    let cipher = match cipher {
+        Cipher::ChaCha20Poly1305 => "ChaChaPoly",
+        #[cfg(feature = "aes-gcm")]
+        Cipher::Aes256Gcm => "AESGCM",
+    };
+    format!("Noise_{pattern}_25519_{cipher}_BLAKE2s")
+        .parse()
+        .expect("valid noise parameters")
+}
+
+impl<T> ChunkIO<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Runs a Noise handshake over `io` and returns the encrypted stream
+    /// together with the peer's static public key. Handshake messages are
+    /// sent as ordinary chunks, so data may follow the last one immediately.
+    pub async fn handshake(
+        io: T,
+        config: &NoiseConfig,
+    ) -> Result<(ChunkIO<T>, [u8; 32]), ChunkIOError> {
+        let mut handshake = snow::Builder::new(noise_params(config.pattern, config.cipher))
+            .local_private_key(&config.private_key)
+            .prologue(PROLOGUE);
+        if let Some(remote_public_key) = config.remote_public_key.as_ref() {
+            handshake = handshake.remote_public_key(remote_public_key);
+        }
+        let mut handshake = match config.initiator {
+            true => handshake.build_initiator()?,
+            false => handshake.build_responder()?,
+        };
+
+        // Handshake messages go out in the clear; the configured encryption,
+        // if any, would only make them unreadable to the peer.
+        let mut builder = config.builder.clone();
+        builder.encryption = None;
+        let mut io = builder.build(io);
+        let mut buf = vec![0; MAX_MESSAGE_LEN];
+        while !handshake.is_handshake_finished() {
+            if handshake.is_my_turn() {
+                let len = handshake.write_message(&[], &mut buf)?;
+                io.send(buf[..len].to_vec()).await?;
+            } else {
+                let message = io.next().await.ok_or(ChunkIOError::Disconnected)??;
+                handshake.read_message(&message, &mut buf)?;
+            }
+        }
+
+        let mut remote_public_key = [0; 32];
+        remote_public_key.copy_from_slice(
+            handshake
+                .get_remote_static()
+                .expect("XX and IK both transmit the remote static key"),
+        );
+        let (initiator_key, responder_key) = handshake.dangerously_get_raw_split();
+        let (send_key, recv_key) = match config.initiator {
+            true => (initiator_key, responder_key),
+            false => (responder_key, initiator_key),
+        };
+        io.codec_mut()
+            .set_encryption(Encryption::new(config.cipher, &send_key, &recv_key));
+        Ok((io, remote_public_key))
+    }
+    /// Negotiates like [`ChunkIO::negotiate`], offering encryption, then runs
+    /// the handshake with the negotiated settings.
+    pub async fn negotiate_handshake(
+        mut io: T,
+        config: &NoiseConfig,
+    ) -> Result<(ChunkIO<T>, [u8; 32], Negotiated), ChunkIOError> {
+        let negotiated = exchange_offering(&mut io, &config.builder, false, true).await?;
+        let config = config
+            .clone()
+            .builder(negotiated.apply(config.builder.clone()));
+        let (io, remote_public_key) = ChunkIO::handshake(io, &config).await?;
+        Ok((io, remote_public_key, negotiated))
+    }
+}
@@ -0,0 +1,761 @@
+use std::ops::Range;
+
+use tokio_util::{
+    bytes::{Buf, BufMut, Bytes, BytesMut},
+    codec::{Decoder, Encoder},
+};
+
+use crate::{
+    builder::{ChunkIOBuilder, DEFAULT_BUFFER_CAPACITY},
+    checksum::Checksum,
+    chunk::Chunk,
+    cipher::{Cipher, Encryption, TAG_LEN},
+    compression::Compression,
+    control::ControlFrame,
+    error::ChunkIOError,
+    fragment::{Defragmenter, Fragment, DEFAULT_MAX_MESSAGE_LENGTH},
+    reorder::Reassembly,
+    write_buf::WriteBuf,
+};
+
+pub const DEFAULT_MAX_CHUNK_LENGTH: u64 = 8 * 1024 * 1024;
+
+// High nibble of the end-of-stream frame; its low nibble is the width of the
+// final offset that follows.
+const END: u8 = 0xe;
+// High nibble of a data chunk header followed by a separate byte of field
+// widths; its low nibble holds flags.
+const EXTENDED: u8 = 0xf;
+const FLAG_COMPRESSED: u8 = 0x1;
+// The chunk starts with its kind and metadata.
+const FLAG_METADATA: u8 = 0x2;
+// Further fragments of the same message follow.
+const FLAG_MORE: u8 = 0x4;
+const KNOWN_FLAGS: u8 = FLAG_COMPRESSED | FLAG_METADATA | FLAG_MORE;
+
+/// An item decoded by [`ChunkIOProto`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Data(Chunk),
+    Control(ControlFrame),
+    /// The peer finished sending; nothing follows. New in protocol version 2,
+    /// see [`ChunkIOBuilder::end_of_stream`].
+    End,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkIOProto {
+    current_index: (u64, u64), // send index, receive index
+    max_chunk_length: u64,
+    strict: bool,
+    checksum: Checksum,
+    encryption: Option<Encryption>,
+    compression: Compression,
+    max_decompressed_length: u64,
+    fragment_size: Option<usize>,
+    message: Defragmenter,
+    reorder: Option<Reassembly>,
+    // Offset of an end-of-stream frame that overtook data before it.
+    end_index: Option<u64>,
+    end_of_stream: bool,
+    // Header of the data chunk at the start of the read buffer, while the
+    // rest of it is still arriving.
+    partial: Option<Header>,
+    // Most read buffer room reserved for a partial chunk at once, so that a
+    // declared length costs no memory until its bytes arrive.
+    read_reserve: usize,
+}
+
+impl Default for ChunkIOProto {
+    fn default() -> Self {
+        ChunkIOProto::new()
+    }
+}
+
+impl ChunkIOProto {
+    pub fn new() -> ChunkIOProto {
+        ChunkIOProto {
+            current_index: (0, 0),
+            max_chunk_length: DEFAULT_MAX_CHUNK_LENGTH,
+            strict: false,
+            checksum: Checksum::None,
+            encryption: None,
+            compression: Compression::None,
+            max_decompressed_length: DEFAULT_MAX_CHUNK_LENGTH,
+            fragment_size: None,
+            message: Defragmenter::new(DEFAULT_MAX_MESSAGE_LENGTH),
+            reorder: None,
+            end_index: None,
+            end_of_stream: true,
+            partial: None,
+            read_reserve: DEFAULT_BUFFER_CAPACITY,
+        }
+    }
+
+    pub fn builder() -> ChunkIOBuilder {
+        ChunkIOBuilder::new()
+    }
+
+    pub(crate) fn from_builder(builder: &ChunkIOBuilder) -> ChunkIOProto {
+        ChunkIOProto {
+            current_index: (0, 0),
+            max_chunk_length: builder.max_chunk_length,
+            strict: builder.strict,
+            checksum: builder.checksum,
+            encryption: builder.encryption.clone(),
+            compression: builder.compression,
+            max_decompressed_length: builder.max_decompressed_length,
+            fragment_size: builder.fragment_size,
+            message: Defragmenter::new(builder.max_message_length),
+            reorder: builder.reorder_capacity.map(Reassembly::new),
+            end_index: None,
+            end_of_stream: builder.end_of_stream,
+            partial: None,
+            read_reserve: builder.read_buffer_capacity.max(1),
+        }
+    }
+
+    /// Returns the send and receive byte offsets.
+    pub fn current_index(&self) -> (u64, u64) {
+        self.current_index
+    }
+
+    pub(crate) fn set_current_index(&mut self, current_index: (u64, u64)) {
+        self.current_index = current_index;
+    }
+
+    pub fn max_chunk_length(&self) -> u64 {
+        self.max_chunk_length
+    }
+
+    pub(crate) fn set_max_chunk_length(&mut self, max_chunk_length: u64) {
+        self.max_chunk_length = max_chunk_length;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Whether closing sends an end-of-stream frame.
+    pub fn end_of_stream(&self) -> bool {
+        self.end_of_stream
+    }
+
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    #[cfg(feature = "noise")]
+    pub(crate) fn set_encryption(&mut self, encryption: Encryption) {
+        self.encryption = Some(encryption);
+    }
+
+    pub fn cipher(&self) -> Option<Cipher> {
+        self.encryption.as_ref().map(Encryption::cipher)
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+}
+
+pub(crate) fn read_be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+/// Number of bytes in the minimal big-endian form of `value`.
+pub(crate) fn be_width(value: u64) -> usize {
+    8 - value.leading_zeros() as usize / 8
+}
+
+fn is_minimal(bytes: &[u8]) -> bool {
+    bytes.first().is_none_or(|b| *b != 0)
+}
+
+/// Fields of a data chunk header.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Header {
+    flags: u8,
+    pub(crate) index: u64,
+    /// Length of the payload and, if sealed, its tag.
+    pub(crate) length: u64,
+    pub(crate) header_len: usize,
+}
+
+impl Header {
+    pub(crate) fn more(&self) -> bool {
+        self.flags & FLAG_MORE != 0
+    }
+}
+
+/// A frame as parsed off the wire, before its offset is checked.
+pub(crate) enum RawFrame {
+    Data {
+        index: u64,
+        fragment: Fragment,
+    },
+    Control(ControlFrame),
+    /// End of the stream after `index` bytes.
+    End {
+        index: u64,
+    },
+}
+
+impl ChunkIOProto {
+    /// Parses the next frame in `src`. With `expected` set, a data chunk at
+    /// any other offset is rejected as soon as its header is complete.
+    ///
+    /// The header of a data chunk that is still arriving is kept until the
+    /// next call, which must pass the same buffer with more bytes appended.
+    /// Room for the rest of the chunk is reserved in `src` a read buffer's
+    /// worth at a time.
+    pub(crate) fn decode_raw(
+        &mut self,
+        src: &mut BytesMut,
+        expected: Option<u64>,
+    ) -> Result<Option<RawFrame>, ChunkIOError> {
+        let header = match self.partial.take() {
+            Some(header) => header,
+            None if src.is_empty() => return Ok(None),
+            None if ControlFrame::is_control(src[0]) => return self.decode_control(src),
+            None if src[0] >> 4 == END => return self.decode_end(src),
+            None => match self.decode_header(src, expected, self.max_chunk_length)? {
+                Some(header) => header,
+                None => return Ok(None),
+            },
+        };
+        let Header {
+            flags,
+            index,
+            length,
+            header_len,
+        } = header;
+        let trailer_len = self.checksum.trailer_len();
+        let frame_len = header_len + length as usize + trailer_len;
+        if src.len() < frame_len {
+            src.reserve((frame_len - src.len()).min(self.read_reserve));
+            self.partial = Some(header);
+            return Ok(None);
+        }
+        let payload_end = header_len + length as usize;
+        if !self.checksum.verify(
+            &src[..header_len],
+            &src[header_len..payload_end],
+            &src[payload_end..payload_end + trailer_len],
+        ) {
+            return Err(ChunkIOError::ChecksumMismatch { offset: index });
+        }
+        let mut payload_len = length as usize;
+        if let Some(encryption) = &self.encryption {
+            let (header, sealed) = src[..payload_end].split_at_mut(header_len);
+            if !encryption.open(index, header, sealed) {
+                return Err(ChunkIOError::AuthenticationFailed { offset: index });
+            }
+            payload_len -= TAG_LEN;
+        }
+        src.advance(header_len);
+        let mut body = src.split_to(payload_len).freeze();
+        src.advance(length as usize - payload_len + trailer_len);
+        if flags & FLAG_COMPRESSED != 0 {
+            body = self
+                .compression
+                .decompress(index, &body, self.max_decompressed_length)?
+                .into();
+        }
+        let fragment = Fragment {
+            body,
+            metadata: flags & FLAG_METADATA != 0,
+            more: flags & FLAG_MORE != 0,
+        };
+        Ok(Some(RawFrame::Data { index, fragment }))
+    }
+
+    /// Forgets the header of a chunk that was cut off, for a buffer that is
+    /// dropped rather than appended to.
+    #[cfg(feature = "udp")]
+    pub(crate) fn discard_partial(&mut self) {
+        self.partial = None;
+    }
+
+    /// Parses the data chunk header at the start of `src`.
+    fn decode_header(
+        &self,
+        src: &[u8],
+        expected: Option<u64>,
+        max_length: u64,
+    ) -> Result<Option<Header>, ChunkIOError> {
+        let (flags, widths_at) = match src[0] >> 4 {
+            EXTENDED => (src[0] & 0xf, 1),
+            _ => (0, 0),
+        };
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        if src.len() <= widths_at {
+            return Ok(None);
+        }
+        let index_pointer = (src[widths_at] >> 4) as usize;
+        let len_pointer = (src[widths_at] & 0xf) as usize;
+        if index_pointer > 8 || len_pointer > 8 {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        let fields_at = widths_at + 1;
+        let header_len = fields_at + index_pointer + len_pointer;
+        if src.len() < header_len {
+            return Ok(None);
+        }
+        let index_bytes = &src[fields_at..fields_at + index_pointer];
+        let len_bytes = &src[fields_at + index_pointer..header_len];
+        if self.strict && !(is_minimal(index_bytes) && is_minimal(len_bytes)) {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        let index = read_be(index_bytes);
+        let length = read_be(len_bytes);
+        if expected.is_some_and(|expected| expected != index) {
+            return Err(ChunkIOError::OutOfOrder);
+        }
+        if length > max_length {
+            return Err(ChunkIOError::ChunkTooLarge {
+                length,
+                max: max_length,
+            });
+        }
+        // Its end has to be addressable.
+        usize::try_from(length)
+            .ok()
+            .and_then(|length| length.checked_add(header_len + self.checksum.trailer_len()))
+            .ok_or(ChunkIOError::ChunkTooLarge {
+                length,
+                max: max_length,
+            })?;
+        Ok(Some(Header {
+            flags,
+            index,
+            length,
+            header_len,
+        }))
+    }
+
+    /// Parses the header of a data chunk whose payload can be handed out as
+    /// it arrives: one that is neither sealed nor compressed nor starts with
+    /// metadata. Anything else in `src` is left to [`Self::decode_raw`].
+    pub(crate) fn decode_streamed_header(
+        &self,
+        src: &[u8],
+        expected: u64,
+        max_length: u64,
+    ) -> Result<Option<Header>, ChunkIOError> {
+        if src.is_empty()
+            || ControlFrame::is_control(src[0])
+            || src[0] >> 4 == END
+            || self.encryption.is_some()
+            || self.partial.is_some()
+        {
+            return Ok(None);
+        }
+        match self.decode_header(src, Some(expected), max_length)? {
+            Some(header) if header.flags & !FLAG_MORE == 0 => Ok(Some(header)),
+            _ => Ok(None),
+        }
+    }
+
+    fn decode_control(&self, src: &mut BytesMut) -> Result<Option<RawFrame>, ChunkIOError> {
+        let Some((frame, mut header_len)) = ControlFrame::decode(src)? else {
+            return Ok(None);
+        };
+        let tag_len = if self.encryption.is_some() {
+            header_len += 8;
+            TAG_LEN
+        } else {
+            0
+        };
+        let trailer_len = self.checksum.trailer_len();
+        if src.len() < header_len + tag_len + trailer_len {
+            return Ok(None);
+        }
+        let tag_end = header_len + tag_len;
+        let offset = self.current_index.1;
+        if !self.checksum.verify(
+            &src[..header_len],
+            &src[header_len..tag_end],
+            &src[tag_end..tag_end + trailer_len],
+        ) {
+            return Err(ChunkIOError::ChecksumMismatch { offset });
+        }
+        if let Some(encryption) = &self.encryption {
+            let sequence = read_be(&src[header_len - 8..header_len]);
+            if !encryption.open_control(sequence, &src[..header_len], &src[header_len..tag_end]) {
+                return Err(ChunkIOError::AuthenticationFailed { offset });
+            }
+        }
+        src.advance(tag_end + trailer_len);
+        Ok(Some(RawFrame::Control(frame)))
+    }
+
+    fn decode_end(&self, src: &mut BytesMut) -> Result<Option<RawFrame>, ChunkIOError> {
+        let width = (src[0] & 0xf) as usize;
+        if width > 8 {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        let header_len = 1 + width;
+        let tag_len = if self.encryption.is_some() {
+            TAG_LEN
+        } else {
+            0
+        };
+        let trailer_len = self.checksum.trailer_len();
+        if src.len() < header_len + tag_len + trailer_len {
+            return Ok(None);
+        }
+        let index_bytes = &src[1..header_len];
+        if self.strict && !is_minimal(index_bytes) {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        let index = read_be(index_bytes);
+        let tag_end = header_len + tag_len;
+        if !self.checksum.verify(
+            &src[..header_len],
+            &src[header_len..tag_end],
+            &src[tag_end..tag_end + trailer_len],
+        ) {
+            return Err(ChunkIOError::ChecksumMismatch { offset: index });
+        }
+        if let Some(encryption) = &self.encryption {
+            if !encryption.open_end(index, &src[..header_len], &src[header_len..tag_end]) {
+                return Err(ChunkIOError::AuthenticationFailed { offset: index });
+            }
+        }
+        src.advance(tag_end + trailer_len);
+        Ok(Some(RawFrame::End { index }))
+    }
+
+    /// Writes a control frame, sealed under the next control sequence number
+    /// if encrypted, followed by its checksum.
+    pub(crate) fn encode_control(&self, frame: ControlFrame, dst: &mut BytesMut) {
+        let start = dst.len();
+        frame.encode(dst);
+        if let Some(encryption) = &self.encryption {
+            let sequence = encryption.next_control_sequence();
+            dst.put_u64(sequence);
+            let tag = encryption.seal_control(sequence, &dst[start..]);
+            dst.extend_from_slice(&tag);
+        }
+        let sum = self.checksum.compute(&dst[start..], &[]);
+        self.checksum.put(sum, dst);
+    }
+
+    /// Writes the end-of-stream frame after the last chunk sent, at `index`,
+    /// unless it is turned off.
+    pub(crate) fn encode_end_at(&self, index: u64, dst: &mut BytesMut) {
+        if !self.end_of_stream {
+            return;
+        }
+        let start = dst.len();
+        let width = be_width(index);
+        dst.extend_from_slice(&[(END << 4) | width as u8]);
+        dst.extend_from_slice(&index.to_be_bytes()[8 - width..]);
+        let header_end = dst.len();
+        if let Some(encryption) = &self.encryption {
+            let tag = encryption.seal_end(index, &dst[start..]);
+            dst.extend_from_slice(&tag);
+        }
+        let sum = self
+            .checksum
+            .compute(&dst[start..header_end], &dst[header_end..]);
+        self.checksum.put(sum, dst);
+    }
+
+    pub(crate) fn encode_end(&self, dst: &mut BytesMut) {
+        self.encode_end_at(self.current_index.0, dst);
+    }
+
+    pub(crate) fn encode_header_at(
+        &self,
+        index: u64,
+        length: usize,
+        flags: u8,
+        dst: &mut BytesMut,
+    ) -> Result<(), ChunkIOError> {
+        if length as u64 > self.max_chunk_length {
+            return Err(ChunkIOError::ChunkTooLarge {
+                length: length as u64,
+                max: self.max_chunk_length,
+            });
+        }
+        let index_width = be_width(index);
+        let length_width = be_width(length as u64);
+        dst.reserve(2 + index_width + length_width);
+        if flags != 0 {
+            dst.put_u8((EXTENDED << 4) | flags);
+        }
+        dst.put_u8(((index_width as u8) << 4) | length_width as u8);
+        dst.put_slice(&index.to_be_bytes()[8 - index_width..]);
+        dst.put_slice(&(length as u64).to_be_bytes()[8 - length_width..]);
+        Ok(())
+    }
+
+    /// Splits a message into the flags and bodies of the chunks it goes out
+    /// as, one after the other.
+    pub(crate) fn fragments(
+        &self,
+        chunk: Chunk,
+    ) -> Result<impl Iterator<Item = (u8, Bytes)>, ChunkIOError> {
+        let (flags, body) = body(chunk)?;
+        Ok(self
+            .fragment_ranges(body.len())?
+            .map(move |(range, more)| (fragment_flags(flags, &range, more), body.slice(range))))
+    }
+
+    /// Ranges of a message body of `len` bytes that are sent as separate
+    /// chunks, each with whether more follow.
+    fn fragment_ranges(
+        &self,
+        len: usize,
+    ) -> Result<impl Iterator<Item = (Range<usize>, bool)>, ChunkIOError> {
+        let size = self.fragment_size.unwrap_or(len).max(1);
+        let count = len.div_ceil(size).max(1);
+        let tag_len = if self.encryption.is_some() {
+            TAG_LEN
+        } else {
+            0
+        };
+        // Checked up front so that a message is never cut off midway.
+        if count > 1 && (size + tag_len) as u64 > self.max_chunk_length {
+            return Err(ChunkIOError::ChunkTooLarge {
+                length: (size + tag_len) as u64,
+                max: self.max_chunk_length,
+            });
+        }
+        Ok((0..count).map(move |i| (i * size..len.min((i + 1) * size), i + 1 < count)))
+    }
+
+    /// Queues a message at `index`, split into fragments if it is larger
+    /// than the fragment size.
+    pub(crate) fn encode_chunk_at(
+        &self,
+        mut index: u64,
+        chunk: Chunk,
+        dst: &mut WriteBuf,
+    ) -> Result<(), ChunkIOError> {
+        for (flags, fragment) in self.fragments(chunk)? {
+            let length = fragment.len() as u64;
+            self.encode_fragment_at(index, flags, fragment, dst)?;
+            index += length;
+        }
+        Ok(())
+    }
+
+    /// Queues a single data chunk without copying large payloads, unless they
+    /// have to be compressed or encrypted.
+    fn encode_fragment_at(
+        &self,
+        index: u64,
+        flags: u8,
+        payload: Bytes,
+        dst: &mut WriteBuf,
+    ) -> Result<(), ChunkIOError> {
+        let (flags, payload) = match self.compression.compress(&payload) {
+            Some(compressed) => (flags | FLAG_COMPRESSED, Bytes::from(compressed)),
+            None => (flags, payload),
+        };
+        if self.encryption.is_some() {
+            return self.encode_copied(index, flags, &payload, dst.tail_mut());
+        }
+        let tail = dst.tail_mut();
+        let start = tail.len();
+        self.encode_header_at(index, payload.len(), flags, tail)?;
+        let sum = self.checksum.compute(&tail[start..], &payload);
+        dst.push(payload);
+        self.checksum.put(sum, dst.tail_mut());
+        Ok(())
+    }
+
+    pub(crate) fn encode_chunk(
+        &mut self,
+        chunk: Chunk,
+        dst: &mut WriteBuf,
+    ) -> Result<(), ChunkIOError> {
+        let length = chunk.stream_len() as u64;
+        self.encode_chunk_at(self.current_index.0, chunk, dst)?;
+        self.current_index.0 += length;
+        Ok(())
+    }
+
+    /// Writes a single data chunk at `index`, copying it after its header.
+    pub(crate) fn encode_fragment_copied(
+        &self,
+        index: u64,
+        flags: u8,
+        payload: &[u8],
+        dst: &mut BytesMut,
+    ) -> Result<(), ChunkIOError> {
+        match self.compression.compress(payload) {
+            Some(compressed) => {
+                self.encode_copied(index, flags | FLAG_COMPRESSED, &compressed, dst)
+            }
+            None => self.encode_copied(index, flags, payload, dst),
+        }
+    }
+
+    /// Writes a data chunk whose payload is already compressed, if flagged,
+    /// copying it after its header.
+    fn encode_copied(
+        &self,
+        index: u64,
+        flags: u8,
+        payload: &[u8],
+        dst: &mut BytesMut,
+    ) -> Result<(), ChunkIOError> {
+        let start = dst.len();
+        let tag_len = if self.encryption.is_some() {
+            TAG_LEN
+        } else {
+            0
+        };
+        self.encode_header_at(index, payload.len() + tag_len, flags, dst)?;
+        let header_end = dst.len();
+        dst.extend_from_slice(payload);
+        if let Some(encryption) = &self.encryption {
+            let (header, payload) = dst[start..].split_at_mut(header_end - start);
+            let tag = encryption.seal(index, header, payload);
+            dst.extend_from_slice(&tag);
+        }
+        let sum = self
+            .checksum
+            .compute(&dst[start..header_end], &dst[header_end..]);
+        self.checksum.put(sum, dst);
+        Ok(())
+    }
+
+    fn encode_slice(
+        &mut self,
+        flags: u8,
+        payload: &[u8],
+        dst: &mut BytesMut,
+    ) -> Result<(), ChunkIOError> {
+        for (range, more) in self.fragment_ranges(payload.len())? {
+            let index = self.current_index.0 + range.start as u64;
+            let flags = fragment_flags(flags, &range, more);
+            self.encode_fragment_copied(index, flags, &payload[range], dst)?;
+        }
+        self.current_index.0 += payload.len() as u64;
+        Ok(())
+    }
+}
+
+// Only the first fragment of a message is flagged as starting with metadata.
+fn fragment_flags(flags: u8, range: &Range<usize>, more: bool) -> u8 {
+    let flags = if range.start == 0 { flags } else { 0 };
+    match more {
+        true => flags | FLAG_MORE,
+        false => flags,
+    }
+}
+
+/// Flags and bytes of a chunk as counted by the offsets, before compression.
+fn body(chunk: Chunk) -> Result<(u8, Bytes), ChunkIOError> {
+    if !chunk.has_section() {
+        return Ok((0, chunk.payload));
+    }
+    let mut body = BytesMut::with_capacity(chunk.stream_len());
+    chunk.encode_section(&mut body)?;
+    body.extend_from_slice(&chunk.payload);
+    Ok((FLAG_METADATA, body.freeze()))
+}
+
+impl Decoder for ChunkIOProto {
+    type Item = Frame;
+
+    type Error = ChunkIOError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            let held = match self.reorder.as_mut() {
+                Some(reorder) => reorder.pop(self.current_index.1)?,
+                None => None,
+            };
+            let fragment = match held {
+                Some(fragment) => fragment,
+                None if self.reorder.is_some() && self.end_index == Some(self.current_index.1) => {
+                    return self.end()
+                }
+                None => {
+                    let expected = self.reorder.is_none().then_some(self.current_index.1);
+                    let (index, fragment) = match self.decode_raw(src, expected)? {
+                        None => return Ok(None),
+                        Some(RawFrame::Control(frame)) => return Ok(Some(Frame::Control(frame))),
+                        Some(RawFrame::End { index }) if index == self.current_index.1 => {
+                            return self.end()
+                        }
+                        // Held until the data before it has arrived.
+                        Some(RawFrame::End { index })
+                            if index > self.current_index.1 && self.reorder.is_some() =>
+                        {
+                            self.end_index = Some(index);
+                            continue;
+                        }
+                        Some(RawFrame::End { .. }) => return Err(ChunkIOError::OutOfOrder),
+                        Some(RawFrame::Data { index, fragment }) => (index, fragment),
+                    };
+                    match self.reorder.as_mut() {
+                        Some(reorder) => {
+                            match reorder.insert(self.current_index.1, index, fragment)? {
+                                Some(ready) => ready,
+                                None => continue,
+                            }
+                        }
+                        None => fragment,
+                    }
+                }
+            };
+            self.current_index.1 += fragment.body.len() as u64;
+            if let Some(chunk) = self.message.push(fragment)? {
+                return Ok(Some(Frame::Data(chunk)));
+            }
+        }
+    }
+}
+
+impl ChunkIOProto {
+    // The peer may not finish the stream in the middle of a message.
+    fn end(&self) -> Result<Option<Frame>, ChunkIOError> {
+        match self.message.is_partial() {
+            true => Err(ChunkIOError::InvalidChunk),
+            false => Ok(Some(Frame::End)),
+        }
+    }
+}
+
+impl Encoder<Bytes> for ChunkIOProto {
+    type Error = ChunkIOError;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_slice(0, &item, dst)
+    }
+}
+
+impl Encoder<Chunk> for ChunkIOProto {
+    type Error = ChunkIOError;
+
+    fn encode(&mut self, item: Chunk, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let (flags, body) = body(item)?;
+        self.encode_slice(flags, &body, dst)
+    }
+}
+
+impl Encoder<ControlFrame> for ChunkIOProto {
+    type Error = ChunkIOError;
+
+    fn encode(&mut self, item: ControlFrame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_control(item, dst);
+        Ok(())
+    }
+}
+
+impl Encoder<Vec<u8>> for ChunkIOProto {
+    type Error = ChunkIOError;
+
+    fn encode(&mut self, item: Vec<u8>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_slice(0, &item, dst)
+    }
+}
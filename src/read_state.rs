@@ -0,0 +1,114 @@
+use std::{
+    collections::VecDeque,
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    chunk::Chunk, control::ControlFrame, error::ChunkIOError, keepalive::Keepalive, proto::Frame,
+};
+use futures::ready;
+
+// Control frames nobody polls for are dropped oldest first past this point.
+const CONTROL_QUEUE_CAPACITY: usize = 64;
+
+/// What the receiving side of a stream keeps besides its transport.
+pub(crate) struct ReadState {
+    control: VecDeque<ControlFrame>,
+    control_waker: Option<Waker>,
+    remote_closed: bool,
+    // Whether the peer ends the stream with an end-of-stream frame, making
+    // an end of the transport before it a disconnect.
+    end_of_stream: bool,
+    keepalive: Option<Keepalive>,
+}
+
+impl ReadState {
+    pub(crate) fn new(end_of_stream: bool, keepalive: Option<Keepalive>) -> ReadState {
+        ReadState {
+            control: VecDeque::new(),
+            control_waker: None,
+            remote_closed: false,
+            end_of_stream,
+            keepalive,
+        }
+    }
+
+    pub(crate) fn poll_control(&mut self, cx: &mut Context) -> Poll<ControlFrame> {
+        match self.control.pop_front() {
+            Some(frame) => Poll::Ready(frame),
+            None => {
+                self.control_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// A stream of decoded frames that answers pings and sends keepalive pings
+/// on its own, shared by [`ChunkIO`](crate::ChunkIO) and
+/// [`ChunkReader`](crate::ChunkReader).
+pub(crate) trait FrameSource {
+    fn read_state(&mut self) -> &mut ReadState;
+
+    fn poll_frame(&mut self, cx: &mut Context) -> Poll<Option<Result<Frame, ChunkIOError>>>;
+
+    /// Queues a control frame sent while reading and writes it out without
+    /// waiting on the transport.
+    fn send_reply(&mut self, frame: ControlFrame, cx: &mut Context) -> Result<(), ChunkIOError>;
+
+    fn poll_chunk(&mut self, cx: &mut Context) -> Poll<Option<Result<Chunk, ChunkIOError>>> {
+        loop {
+            if self.read_state().remote_closed {
+                return Poll::Ready(None);
+            }
+            let frame = match self.poll_frame(cx) {
+                Poll::Ready(frame) => frame,
+                Poll::Pending => {
+                    let Some(keepalive) = self.read_state().keepalive.as_mut() else {
+                        return Poll::Pending;
+                    };
+                    let ping = ready!(keepalive.poll(cx))?;
+                    self.send_reply(ping, cx)?;
+                    continue;
+                }
+            };
+            if let Some(keepalive) = self.read_state().keepalive.as_mut() {
+                keepalive.on_activity();
+            }
+            match frame {
+                Some(Ok(Frame::Data(chunk))) => return Poll::Ready(Some(Ok(chunk))),
+                Some(Ok(Frame::Control(frame))) => self.on_control(frame, cx)?,
+                Some(Ok(Frame::End)) => self.read_state().remote_closed = true,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => {
+                    let state = self.read_state();
+                    state.remote_closed = true;
+                    if state.end_of_stream {
+                        // The transport ended before the peer finished the
+                        // stream.
+                        return Poll::Ready(Some(Err(ChunkIOError::Disconnected)));
+                    }
+                    return Poll::Ready(None);
+                }
+            }
+        }
+    }
+
+    fn on_control(&mut self, frame: ControlFrame, cx: &mut Context) -> Result<(), ChunkIOError> {
+        if let ControlFrame::Ping(value) = frame {
+            self.send_reply(ControlFrame::Pong(value), cx)?;
+        }
+        let state = self.read_state();
+        if state.control.len() == CONTROL_QUEUE_CAPACITY {
+            state.control.pop_front();
+        }
+        state.control.push_back(frame);
+        if let Some(waker) = state.control_waker.take() {
+            waker.wake();
+        }
+        match frame {
+            ControlFrame::Reset(_) => Err(ChunkIOError::Reset),
+            _ => Ok(()),
+        }
+    }
+}
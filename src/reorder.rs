@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+use crate::{error::ChunkIOError, fragment::Fragment};
+
+// Empty chunks count as one byte so that only so many can be held.
+fn held_size(chunk: &Fragment) -> usize {
+    chunk.body.len().max(1)
+}
+
+fn has_data(chunk: &Fragment) -> bool {
+    !chunk.body.is_empty()
+}
+
+/// Holds chunks that arrived ahead of the receive offset until the gap before
+/// them is filled.
+#[derive(Debug, Clone)]
+pub(crate) struct Reassembly {
+    // Keyed by offset and whether the chunk has data, so an empty chunk comes
+    // out before the chunk that starts where it was sent.
+    held: BTreeMap<(u64, bool), Fragment>,
+    held_len: usize,
+    capacity: usize,
+}
+
+impl Reassembly {
+    pub(crate) fn new(capacity: usize) -> Reassembly {
+        Reassembly {
+            held: BTreeMap::new(),
+            held_len: 0,
+            capacity,
+        }
+    }
+
+    /// Takes a chunk that starts at `index` while the receiver expects
+    /// `next`, and returns it if it can be delivered right away. Exact
+    /// duplicates of delivered or held chunks are dropped. Empty chunks
+    /// cannot be told from their duplicates: one at the receive offset is
+    /// always delivered, but of those held ahead of it at one offset only the
+    /// first is kept.
+    pub(crate) fn insert(
+        &mut self,
+        next: u64,
+        index: u64,
+        chunk: Fragment,
+    ) -> Result<Option<Fragment>, ChunkIOError> {
+        if index == next && has_data(&chunk) {
+            // An empty chunk held at this offset was sent first.
+            if let Some(empty) = self.held.remove(&(next, false)) {
+                self.hold(index, chunk)?;
+                return Ok(Some(empty));
+            }
+        }
+        if index == next {
+            return Ok(Some(chunk));
+        }
+        if index < next {
+            return match index.checked_add(chunk.body.len() as u64) {
+                Some(end) if end <= next => Ok(None),
+                _ => Err(ChunkIOError::OutOfOrder),
+            };
+        }
+        if let Some(held) = self.held.get(&(index, has_data(&chunk))) {
+            return match held.body.len() == chunk.body.len() {
+                true => Ok(None),
+                false => Err(ChunkIOError::OutOfOrder),
+            };
+        }
+        self.hold(index, chunk)?;
+        Ok(None)
+    }
+
+    fn hold(&mut self, index: u64, chunk: Fragment) -> Result<(), ChunkIOError> {
+        if self.held_len + held_size(&chunk) > self.capacity {
+            return Err(ChunkIOError::ReassemblyFull);
+        }
+        self.held_len += held_size(&chunk);
+        self.held.insert((index, has_data(&chunk)), chunk);
+        Ok(())
+    }
+
+    /// Removes the held chunk that starts at `next`, if any.
+    pub(crate) fn pop(&mut self, next: u64) -> Result<Option<Fragment>, ChunkIOError> {
+        let Some(entry) = self.held.first_entry() else {
+            return Ok(None);
+        };
+        if entry.key().0 > next {
+            return Ok(None);
+        }
+        if entry.key().0 < next {
+            return Err(ChunkIOError::OutOfOrder);
+        }
+        let chunk = entry.remove();
+        self.held_len -= held_size(&chunk);
+        Ok(Some(chunk))
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::VecDeque,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use futures::{ready, Sink, SinkExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{ChunkIO, ChunkIOBuilder, ChunkIOError};
+
+const FRAME_DATA: u8 = 0;
+const FRAME_ACK: u8 = 1;
+const FRAME_CLOSE: u8 = 2;
+
+pub const DEFAULT_RETRANSMIT_CAPACITY: usize = 1024 * 1024;
+
+/// A logical chunk stream that survives transport reconnects.
+///
+/// Every chunk sent is kept until the peer acknowledges its offset. After a
+/// transport fails, hand a fresh one to [`ResumableChunkIO::resume`]: both
+/// sides exchange the offset they have received up to and the unacknowledged
+/// tail is replayed from there.
+///
+/// Session offsets count data and close frames only; acknowledgements are
+/// neither buffered nor acknowledged themselves. Chunk offsets on the wire
+/// keep growing across transports, so no offset is reused for a new frame.
+pub struct ResumableChunkIO<T, I = Vec<u8>> {
+    inner: Option<ChunkIO<T, Bytes>>,
+    builder: ChunkIOBuilder,
+    retransmit: VecDeque<(u64, Bytes)>, // offset, session frame
+    retransmit_len: usize,
+    retransmit_capacity: usize,
+    current_index: (u64, u64), // send index, receive index
+    wire_index: (u64, u64),    // chunk offsets of the last transport
+    acked_index: u64,
+    ack_interval: u64,
+    inbound: VecDeque<Bytes>,
+    inbound_len: usize,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+    close_sent: bool,
+    closed: bool,
+    _item: PhantomData<fn(I) -> I>,
+}
+
+impl<T, I> ResumableChunkIO<T, I>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Creates a disconnected session; attach the first transport with
+    /// [`ResumableChunkIO::resume`].
+    pub fn new(builder: ChunkIOBuilder, retransmit_capacity: usize) -> ResumableChunkIO<T, I> {
+        ResumableChunkIO {
+            inner: None,
+            builder,
+            retransmit: VecDeque::new(),
+            retransmit_len: 0,
+            retransmit_capacity,
+            current_index: (0, 0),
+            wire_index: (0, 0),
+            acked_index: 0,
+            ack_interval: (retransmit_capacity / 2) as u64,
+            inbound: VecDeque::new(),
+            inbound_len: 0,
+            read_waker: None,
+            write_waker: None,
+            close_sent: false,
+            closed: false,
+            _item: PhantomData,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// Returns the send and receive byte offsets of the session.
+    pub fn current_index(&self) -> (u64, u64) {
+        self.current_index
+    }
+
+    /// Attaches a new transport, replacing the previous one, and replays every
+    /// chunk the peer has not received yet.
+    pub async fn resume(&mut self, mut io: T) -> Result<(), ChunkIOError> {
+        self.detach();
+        let mut hello = [0u8; 24];
+        hello[..8].copy_from_slice(&self.current_index.1.to_be_bytes());
+        hello[8..16].copy_from_slice(&(self.retransmit_capacity as u64).to_be_bytes());
+        hello[16..].copy_from_slice(&self.wire_index.0.to_be_bytes());
+        io.write_all(&hello).await?;
+        io.flush().await?;
+        io.read_exact(&mut hello).await?;
+        let peer_index = u64::from_be_bytes(hello[..8].try_into().unwrap());
+        let peer_capacity = u64::from_be_bytes(hello[8..16].try_into().unwrap());
+        let peer_wire_index = u64::from_be_bytes(hello[16..].try_into().unwrap());
+        if peer_wire_index < self.wire_index.1 {
+            return Err(ChunkIOError::InvalidResumeOffset(peer_wire_index));
+        }
+
+        let buffered_from = self.current_index.0 - self.retransmit_len as u64;
+        if peer_index < buffered_from || peer_index > self.current_index.0 {
+            return Err(ChunkIOError::InvalidResumeOffset(peer_index));
+        }
+        self.acknowledge(peer_index);
+        if let Some((offset, _)) = self.retransmit.front() {
+            if *offset != peer_index {
+                return Err(ChunkIOError::InvalidResumeOffset(peer_index));
+            }
+        }
+        self.ack_interval = (peer_capacity / 2).max(1);
+        self.acked_index = self.current_index.1;
+
+        let mut inner = self.builder.build_bytes(io);
+        inner
+            .codec_mut()
+            .set_current_index((self.wire_index.0, peer_wire_index));
+        for (_, frame) in self.retransmit.iter() {
+            inner.feed(frame.clone()).await?;
+        }
+        inner.flush().await?;
+        self.inner = Some(inner);
+        self.wake();
+        Ok(())
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn detach(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            self.wire_index = inner.codec().current_index();
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.detach();
+        self.wake();
+    }
+
+    fn acknowledge(&mut self, index: u64) {
+        while let Some((offset, frame)) = self.retransmit.front() {
+            if offset + frame.len() as u64 > index {
+                break;
+            }
+            self.retransmit_len -= frame.len();
+            self.retransmit.pop_front();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn send_frame(&mut self, frame: Bytes) -> Result<(), ChunkIOError> {
+        let inner = self.inner.as_mut().ok_or(ChunkIOError::Disconnected)?;
+        inner.start_send_unpin(frame.clone())?;
+        self.retransmit_len += frame.len();
+        self.retransmit
+            .push_back((self.current_index.0, frame.clone()));
+        self.current_index.0 += frame.len() as u64;
+        Ok(())
+    }
+
+    fn poll_frame(&mut self, cx: &mut Context) -> Poll<Result<Option<Bytes>, ChunkIOError>> {
+        loop {
+            if self.closed {
+                return Poll::Ready(Ok(None));
+            }
+            let inner = self.inner.as_mut().ok_or(ChunkIOError::Disconnected)?;
+            let mut frame = match ready!(inner.poll_next_unpin(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(e)) => {
+                    self.disconnect();
+                    return Poll::Ready(Err(e));
+                }
+                None => {
+                    self.disconnect();
+                    return Poll::Ready(Err(ChunkIOError::Disconnected));
+                }
+            };
+            if !frame.has_remaining() {
+                return Poll::Ready(Err(ChunkIOError::InvalidChunk));
+            }
+            let frame_len = frame.len() as u64;
+            let data = match frame.get_u8() {
+                FRAME_DATA => Some(frame),
+                FRAME_ACK if frame.len() == 8 => {
+                    self.acknowledge(frame.get_u64());
+                    continue;
+                }
+                FRAME_CLOSE => {
+                    self.closed = true;
+                    None
+                }
+                _ => return Poll::Ready(Err(ChunkIOError::InvalidChunk)),
+            };
+            self.current_index.1 += frame_len;
+            self.poll_send_ack(cx)?;
+            if let Some(data) = data {
+                return Poll::Ready(Ok(Some(data)));
+            }
+        }
+    }
+
+    fn push_inbound(&mut self, data: Bytes) {
+        self.inbound_len += data.len();
+        self.inbound.push_back(data);
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Takes in the frames that have already arrived, so the peer gets its
+    /// acknowledgements while this side only writes. Up to a retransmit
+    /// capacity's worth of data is held for the reader; a parked reader reads
+    /// and acknowledges on its own.
+    fn poll_receive(&mut self, cx: &mut Context) -> Result<(), ChunkIOError> {
+        while self.read_waker.is_none() && self.inbound_len < self.retransmit_capacity {
+            match self.poll_frame(cx) {
+                Poll::Ready(Ok(Some(data))) => self.push_inbound(data),
+                Poll::Ready(Ok(None)) | Poll::Pending => break,
+                Poll::Ready(Err(e)) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_send_ack(&mut self, cx: &mut Context) -> Result<(), ChunkIOError> {
+        if self.current_index.1 - self.acked_index < self.ack_interval {
+            return Ok(());
+        }
+        self.acked_index = self.current_index.1;
+        let mut frame = BytesMut::with_capacity(9);
+        frame.put_u8(FRAME_ACK);
+        frame.put_u64(self.acked_index);
+        // Acknowledgements are not replayed; the resume hello carries the
+        // receive offset instead.
+        let inner = self.inner.as_mut().ok_or(ChunkIOError::Disconnected)?;
+        inner.start_send_unpin(frame.freeze())?;
+        if let Poll::Ready(Err(e)) = inner.poll_flush_unpin(cx) {
+            self.disconnect();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn poll_inner<F>(&mut self, cx: &mut Context, f: F) -> Poll<Result<(), ChunkIOError>>
+    where
+        F: FnOnce(Pin<&mut ChunkIO<T, Bytes>>, &mut Context) -> Poll<Result<(), ChunkIOError>>,
+    {
+        let inner = self.inner.as_mut().ok_or(ChunkIOError::Disconnected)?;
+        let result = ready!(f(Pin::new(inner), cx));
+        if result.is_err() {
+            self.disconnect();
+        }
+        Poll::Ready(result)
+    }
+}
+
+impl<T, I> Stream for ResumableChunkIO<T, I>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    I: From<Bytes>,
+{
+    type Item = Result<I, ChunkIOError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if let Some(data) = this.inbound.pop_front() {
+            this.inbound_len -= data.len();
+            return Poll::Ready(Some(Ok(I::from(data))));
+        }
+        match this.poll_frame(cx) {
+            Poll::Ready(frame) => {
+                this.read_waker = None;
+                Poll::Ready(frame.transpose().map(|frame| frame.map(I::from)))
+            }
+            Poll::Pending => {
+                this.read_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T, I> Sink<I> for ResumableChunkIO<T, I>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    I: Into<Bytes>,
+{
+    type Error = ChunkIOError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = &mut *self;
+        this.poll_receive(cx)?;
+        while this.retransmit_len >= this.retransmit_capacity {
+            // The peer only acknowledges what it has received, so push out
+            // everything buffered before waiting on its acks.
+            ready!(this.poll_inner(cx, |inner, cx| inner.poll_flush(cx)))?;
+            match this.poll_frame(cx) {
+                Poll::Ready(Ok(Some(data))) => this.push_inbound(data),
+                Poll::Ready(Ok(None)) => return Poll::Ready(Err(ChunkIOError::Disconnected)),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                // An acknowledgement read before the transport ran dry may
+                // already have made room.
+                Poll::Pending if this.retransmit_len < this.retransmit_capacity => break,
+                Poll::Pending => {
+                    this.write_waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+        this.poll_inner(cx, |inner, cx| inner.poll_ready(cx))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let payload = item.into();
+        let mut frame = BytesMut::with_capacity(1 + payload.len());
+        frame.put_u8(FRAME_DATA);
+        frame.extend_from_slice(&payload);
+        self.send_frame(frame.freeze())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.poll_receive(cx)?;
+        self.poll_inner(cx, |inner, cx| inner.poll_flush(cx))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = &mut *self;
+        if !this.close_sent {
+            this.send_frame(Bytes::from_static(&[FRAME_CLOSE]))?;
+            this.close_sent = true;
+        }
+        this.poll_inner(cx, |inner, cx| inner.poll_close(cx))
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    error::Error,
+    fmt,
+    future::poll_fn,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use futures::{ready, Sink, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio_util::{bytes::Buf, codec::Framed};
+
+use crate::{
+    chunk::Chunk,
+    control::ControlFrame,
+    framed,
+    mux::WakerSet,
+    proto::Frame,
+    read_state::{FrameSource, ReadState},
+    write_buf::WriteBuf,
+    ChunkIO, ChunkIOError, ChunkIOProto,
+};
+
+struct WriteState<T> {
+    io: WriteHalf<T>,
+    write_buf: WriteBuf,
+    flush_control: bool,
+}
+
+impl<T> WriteState<T>
+where
+    T: AsyncWrite,
+{
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), ChunkIOError>> {
+        ready!(self.write_buf.poll_write_to(&mut self.io, cx))?;
+        ready!(Pin::new(&mut self.io).poll_flush(cx))?;
+        self.flush_control = false;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Writes out replies queued by the reader without waiting on the
+    /// transport.
+    fn poll_flush_control(&mut self, cx: &mut Context) -> Result<(), ChunkIOError> {
+        if self.flush_control {
+            if let Poll::Ready(result) = self.poll_flush(cx) {
+                result?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The write side of a split stream. The reader answers pings through it
+/// as well, always between whole chunks.
+struct Shared<T> {
+    state: Mutex<WriteState<T>>,
+    wakers: Arc<WakerSet>,
+}
+
+impl<T> Shared<T> {
+    fn poll_write<F, R>(&self, cx: &mut Context, f: F) -> R
+    where
+        F: FnOnce(&mut WriteState<T>, &mut Context) -> R,
+    {
+        // Both halves may be waiting on the transport, which only remembers
+        // the most recent waker.
+        self.wakers.register(cx.waker());
+        let waker = Waker::from(self.wakers.clone());
+        f(
+            &mut self.state.lock().unwrap(),
+            &mut Context::from_waker(&waker),
+        )
+    }
+}
+
+/// Receiving half of a [`ChunkIO`], see [`ChunkIO::into_split`].
+pub struct ChunkReader<T, I = Vec<u8>> {
+    inner: Framed<ReadHalf<T>, ChunkIOProto>,
+    read: ReadState,
+    shared: Arc<Shared<T>>,
+    _item: PhantomData<fn(I) -> I>,
+}
+
+/// Sending half of a [`ChunkIO`], see [`ChunkIO::into_split`].
+pub struct ChunkWriter<T, I = Vec<u8>> {
+    codec: ChunkIOProto,
+    backpressure_boundary: usize,
+    local_closed: bool,
+    shared: Arc<Shared<T>>,
+    _item: PhantomData<fn(I) -> I>,
+}
+
+/// Returned by [`ChunkReader::reunite`] when the halves belong to different
+/// streams.
+pub struct ReuniteError<T, I = Vec<u8>>(pub ChunkReader<T, I>, pub ChunkWriter<T, I>);
+
+impl<T, I> fmt::Debug for ReuniteError<T, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReuniteError").finish_non_exhaustive()
+    }
+}
+
+impl<T, I> fmt::Display for ReuniteError<T, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("tried to reunite halves of different streams")
+    }
+}
+
+impl<T, I> Error for ReuniteError<T, I> {}
+
+impl<T, I> ChunkIO<T, I> {
+    /// Splits the stream into a reader and a writer that can be used from
+    /// different tasks. Each keeps its own offset; pings received by the
+    /// reader are still answered through the writer's transport.
+    pub fn into_split(self) -> (ChunkReader<T, I>, ChunkWriter<T, I>)
+    where
+        T: AsyncRead + AsyncWrite,
+    {
+        let parts = self.inner.into_parts();
+        let (read, write) = tokio::io::split(parts.io);
+        let shared = Arc::new(Shared {
+            state: Mutex::new(WriteState {
+                io: write,
+                write_buf: self.write_buf,
+                flush_control: self.flush_control,
+            }),
+            wakers: Arc::default(),
+        });
+        let writer = ChunkWriter {
+            codec: parts.codec.clone(),
+            backpressure_boundary: self.backpressure_boundary,
+            local_closed: self.local_closed,
+            shared: shared.clone(),
+            _item: PhantomData,
+        };
+        let reader = ChunkReader {
+            inner: framed(read, parts.codec, parts.read_buf),
+            read: self.read,
+            shared,
+            _item: PhantomData,
+        };
+        (reader, writer)
+    }
+}
+
+impl<T, I> ChunkReader<T, I> {
+    /// Only the receive offset of this codec is kept up to date.
+    pub fn codec(&self) -> &ChunkIOProto {
+        self.inner.codec()
+    }
+
+    /// See [`ChunkIO::poll_control`].
+    pub fn poll_control(&mut self, cx: &mut Context) -> Poll<ControlFrame> {
+        self.read.poll_control(cx)
+    }
+
+    pub fn is_pair_of(&self, writer: &ChunkWriter<T, I>) -> bool {
+        Arc::ptr_eq(&self.shared, &writer.shared)
+    }
+
+    /// Joins the halves back into the stream they were split from.
+    #[allow(clippy::result_large_err)]
+    pub fn reunite(self, writer: ChunkWriter<T, I>) -> Result<ChunkIO<T, I>, ReuniteError<T, I>>
+    where
+        T: Unpin,
+    {
+        if !self.is_pair_of(&writer) {
+            return Err(ReuniteError(self, writer));
+        }
+        drop(self.shared);
+        let state = Arc::into_inner(writer.shared)
+            .expect("no other references to the halves' shared state")
+            .state
+            .into_inner()
+            .unwrap();
+        let parts = self.inner.into_parts();
+        let mut codec = parts.codec;
+        codec.set_current_index((writer.codec.current_index().0, codec.current_index().1));
+        Ok(ChunkIO {
+            inner: framed(parts.io.unsplit(state.io), codec, parts.read_buf),
+            write_buf: state.write_buf,
+            backpressure_boundary: writer.backpressure_boundary,
+            read: self.read,
+            local_closed: writer.local_closed,
+            flush_control: state.flush_control,
+            _item: PhantomData,
+        })
+    }
+}
+
+impl<T, I> FrameSource for ChunkReader<T, I>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    fn read_state(&mut self) -> &mut ReadState {
+        &mut self.read
+    }
+
+    fn poll_frame(&mut self, cx: &mut Context) -> Poll<Option<Result<Frame, ChunkIOError>>> {
+        self.inner.poll_next_unpin(cx)
+    }
+
+    fn send_reply(&mut self, frame: ControlFrame, cx: &mut Context) -> Result<(), ChunkIOError> {
+        let codec = self.inner.codec();
+        self.shared.poll_write(cx, |state, cx| {
+            codec.encode_control(frame, state.write_buf.tail_mut());
+            state.flush_control = true;
+            state.poll_flush_control(cx)
+        })
+    }
+}
+
+impl<T, I> Stream for ChunkReader<T, I>
+where
+    T: AsyncRead + AsyncWrite,
+    I: From<Chunk>,
+{
+    type Item = Result<I, ChunkIOError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.shared
+            .poll_write(cx, |state, cx| state.poll_flush_control(cx))?;
+        self.poll_chunk(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map(I::from)))
+    }
+}
+
+impl<T, I> ChunkWriter<T, I> {
+    /// Only the send offset of this codec is kept up to date.
+    pub fn codec(&self) -> &ChunkIOProto {
+        &self.codec
+    }
+
+    /// Queues a control frame; it goes out with the next flush.
+    pub fn send_control(&mut self, frame: ControlFrame) {
+        self.codec.encode_control(
+            frame,
+            self.shared.state.lock().unwrap().write_buf.tail_mut(),
+        );
+    }
+
+    /// See [`ChunkIO::poll_close_write`].
+    pub fn poll_close_write(&mut self, cx: &mut Context) -> Poll<Result<(), ChunkIOError>>
+    where
+        T: AsyncWrite,
+    {
+        if !self.local_closed {
+            let mut state = self.shared.state.lock().unwrap();
+            self.codec.encode_end(state.write_buf.tail_mut());
+            self.local_closed = true;
+        }
+        let end_of_stream = self.codec.end_of_stream();
+        self.shared.poll_write(cx, |state, cx| {
+            ready!(state.poll_flush(cx))?;
+            match end_of_stream {
+                true => Poll::Ready(Ok(())),
+                false => Pin::new(&mut state.io)
+                    .poll_shutdown(cx)
+                    .map_err(Into::into),
+            }
+        })
+    }
+
+    /// See [`ChunkIO::poll_close_write`].
+    pub async fn close_write(&mut self) -> Result<(), ChunkIOError>
+    where
+        T: AsyncWrite,
+    {
+        poll_fn(|cx| self.poll_close_write(cx)).await
+    }
+
+    /// Joins the halves back into the stream they were split from.
+    #[allow(clippy::result_large_err)]
+    pub fn reunite(self, reader: ChunkReader<T, I>) -> Result<ChunkIO<T, I>, ReuniteError<T, I>>
+    where
+        T: Unpin,
+    {
+        reader.reunite(self)
+    }
+}
+
+impl<T, I> Sink<I> for ChunkWriter<T, I>
+where
+    T: AsyncWrite,
+    I: Into<Chunk>,
+{
+    type Error = ChunkIOError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let boundary = self.backpressure_boundary;
+        self.shared.poll_write(cx, |state, cx| {
+            if state.write_buf.remaining() >= boundary {
+                ready!(state.write_buf.poll_write_to(&mut state.io, cx))?;
+            }
+            Poll::Ready(Ok(()))
+        })
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let this = &mut *self;
+        if this.local_closed {
+            return Err(ChunkIOError::StreamClosed);
+        }
+        let mut state = this.shared.state.lock().unwrap();
+        this.codec.encode_chunk(item.into(), &mut state.write_buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.shared.poll_write(cx, |state, cx| state.poll_flush(cx))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_close_write(cx))?;
+        if !self.codec.end_of_stream() {
+            // Already shut down.
+            return Poll::Ready(Ok(()));
+        }
+        self.shared.poll_write(cx, |state, cx| {
+            Pin::new(&mut state.io)
+                .poll_shutdown(cx)
+                .map_err(Into::into)
+        })
+    }
+}
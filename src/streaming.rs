@@ -0,0 +1,312 @@
+use std::{
+    future::poll_fn,
+    io, mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{ready, Stream};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_util::{
+    bytes::{Buf, Bytes, BytesMut},
+    io::poll_read_buf,
+};
+
+use crate::{
+    checksum::Digest,
+    chunk::Chunk,
+    control::ControlFrame,
+    proto::{ChunkIOProto, RawFrame, DEFAULT_MAX_CHUNK_LENGTH},
+    ChunkIOBuilder, ChunkIOError,
+};
+
+/// Header of a chunk read by a [`StreamingReader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkHeader {
+    pub index: u64,
+    /// Length of the payload that follows.
+    pub length: u64,
+    pub kind: u16,
+    pub metadata: Vec<(String, Bytes)>,
+    /// The chunk is a fragment of a message that continues in the next one.
+    pub more: bool,
+}
+
+enum State {
+    Idle,
+    /// Payload of a plain chunk, handed out as it is read.
+    Streamed {
+        index: u64,
+        remaining: u64,
+        digest: Digest,
+    },
+    /// Payload of a chunk that had to be read whole.
+    Buffered(Bytes),
+}
+
+/// Reads chunks without buffering their payloads.
+///
+/// [`next_chunk`](Self::next_chunk) returns the header of the next chunk and
+/// [`payload`](Self::payload) its payload, piece by piece or as an
+/// `AsyncRead`. Only plain chunks are streamed, bounded by the maximum chunk
+/// length; sealed and compressed ones, and those starting with metadata, are
+/// read whole and bounded by a separate, usually smaller limit. The checksum
+/// is verified at the end of the payload, so on a mismatch the pieces handed
+/// out before have to be discarded.
+/// Fragments are not joined, and since nothing is sent back, pings go
+/// unanswered.
+pub struct StreamingReader<R> {
+    io: R,
+    // Bounds the chunks read whole; streamed ones are bounded by
+    // `max_chunk_length`.
+    codec: ChunkIOProto,
+    max_chunk_length: u64,
+    read_buf: BytesMut,
+    read_capacity: usize,
+    state: State,
+    // Unread rest of the piece last copied out through `AsyncRead`.
+    piece: Bytes,
+    receive_index: u64,
+    finished: bool,
+}
+
+impl<R> StreamingReader<R> {
+    /// Reads chunks whole up to the builder's maximum chunk length or
+    /// [`DEFAULT_MAX_CHUNK_LENGTH`], whichever is smaller.
+    pub fn new(io: R, builder: &ChunkIOBuilder) -> StreamingReader<R> {
+        let max_buffered_length = builder.max_chunk_length.min(DEFAULT_MAX_CHUNK_LENGTH);
+        StreamingReader::with_max_buffered_length(io, builder, max_buffered_length)
+    }
+
+    /// Uses `max_buffered_length` as the limit for chunks that are read whole.
+    pub fn with_max_buffered_length(
+        io: R,
+        builder: &ChunkIOBuilder,
+        max_buffered_length: u64,
+    ) -> StreamingReader<R> {
+        let mut codec = builder.codec();
+        codec.set_max_chunk_length(max_buffered_length);
+        StreamingReader {
+            io,
+            codec,
+            max_chunk_length: builder.max_chunk_length,
+            read_buf: BytesMut::with_capacity(builder.read_buffer_capacity),
+            read_capacity: builder.read_buffer_capacity.max(1),
+            state: State::Idle,
+            piece: Bytes::new(),
+            receive_index: 0,
+            finished: false,
+        }
+    }
+
+    /// Returns the receive byte offset, counting chunks whose header was
+    /// returned.
+    pub fn current_index(&self) -> u64 {
+        self.receive_index
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.io
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.io
+    }
+
+    pub fn into_inner(self) -> R {
+        self.io
+    }
+}
+
+impl<R> StreamingReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Skips what is left of the current payload and returns the header of
+    /// the next chunk, or `None` once the peer finished the stream.
+    pub async fn next_chunk(&mut self) -> Result<Option<ChunkHeader>, ChunkIOError> {
+        poll_fn(|cx| self.poll_next_chunk(cx)).await
+    }
+
+    pub fn poll_next_chunk(
+        &mut self,
+        cx: &mut Context,
+    ) -> Poll<Result<Option<ChunkHeader>, ChunkIOError>> {
+        while ready!(self.poll_piece(cx))?.is_some() {}
+        loop {
+            if self.finished {
+                return Poll::Ready(Ok(None));
+            }
+            if let Some(header) = self.codec.decode_streamed_header(
+                &self.read_buf,
+                self.receive_index,
+                self.max_chunk_length,
+            )? {
+                let digest = self
+                    .codec
+                    .checksum()
+                    .digest(&self.read_buf[..header.header_len]);
+                self.read_buf.advance(header.header_len);
+                self.receive_index += header.length;
+                self.state = State::Streamed {
+                    index: header.index,
+                    remaining: header.length,
+                    digest,
+                };
+                return Poll::Ready(Ok(Some(ChunkHeader {
+                    index: header.index,
+                    length: header.length,
+                    kind: 0,
+                    metadata: Vec::new(),
+                    more: header.more(),
+                })));
+            }
+            match self
+                .codec
+                .decode_raw(&mut self.read_buf, Some(self.receive_index))?
+            {
+                Some(RawFrame::Data { index, fragment }) => {
+                    self.receive_index += fragment.body.len() as u64;
+                    let chunk = match fragment.metadata {
+                        true => Chunk::decode(fragment.body)?,
+                        false => Chunk::new(fragment.body),
+                    };
+                    let header = ChunkHeader {
+                        index,
+                        length: chunk.payload.len() as u64,
+                        kind: chunk.kind,
+                        metadata: chunk.metadata,
+                        more: fragment.more,
+                    };
+                    self.state = State::Buffered(chunk.payload);
+                    return Poll::Ready(Ok(Some(header)));
+                }
+                Some(RawFrame::End { index }) if index == self.receive_index => {
+                    self.finished = true
+                }
+                Some(RawFrame::End { .. }) => return Poll::Ready(Err(ChunkIOError::OutOfOrder)),
+                Some(RawFrame::Control(ControlFrame::Reset(_))) => {
+                    return Poll::Ready(Err(ChunkIOError::Reset))
+                }
+                Some(RawFrame::Control(_)) => {}
+                None => ready!(self.poll_fill(cx))?,
+            }
+        }
+    }
+
+    /// Returns the next piece of the current payload, or `None` at its end.
+    pub fn poll_piece(&mut self, cx: &mut Context) -> Poll<Result<Option<Bytes>, ChunkIOError>> {
+        if !self.piece.is_empty() {
+            return Poll::Ready(Ok(Some(mem::take(&mut self.piece))));
+        }
+        loop {
+            match &mut self.state {
+                State::Idle => return Poll::Ready(Ok(None)),
+                State::Buffered(payload) => {
+                    let payload = mem::take(payload);
+                    self.state = State::Idle;
+                    if !payload.is_empty() {
+                        return Poll::Ready(Ok(Some(payload)));
+                    }
+                }
+                State::Streamed {
+                    index,
+                    remaining: 0,
+                    digest,
+                } => {
+                    let trailer_len = self.codec.checksum().trailer_len();
+                    if self.read_buf.len() >= trailer_len {
+                        if !digest.verify(&self.read_buf[..trailer_len]) {
+                            return Poll::Ready(Err(ChunkIOError::ChecksumMismatch {
+                                offset: *index,
+                            }));
+                        }
+                        self.read_buf.advance(trailer_len);
+                        self.state = State::Idle;
+                        return Poll::Ready(Ok(None));
+                    }
+                }
+                State::Streamed {
+                    remaining, digest, ..
+                } if !self.read_buf.is_empty() => {
+                    let len = (*remaining).min(self.read_buf.len() as u64) as usize;
+                    let piece = self.read_buf.split_to(len).freeze();
+                    digest.update(&piece);
+                    *remaining -= len as u64;
+                    return Poll::Ready(Ok(Some(piece)));
+                }
+                State::Streamed { .. } => {}
+            }
+            if matches!(self.state, State::Streamed { .. }) {
+                ready!(self.poll_fill(cx))?;
+            }
+        }
+    }
+
+    /// Waits for more bytes from the transport.
+    fn poll_fill(&mut self, cx: &mut Context) -> Poll<Result<(), ChunkIOError>> {
+        if self.read_buf.capacity() == self.read_buf.len() {
+            self.read_buf.reserve(self.read_capacity);
+        }
+        if ready!(poll_read_buf(
+            Pin::new(&mut self.io),
+            cx,
+            &mut self.read_buf
+        ))? == 0
+        {
+            let clean = self.read_buf.is_empty() && matches!(self.state, State::Idle);
+            self.finished = true;
+            self.state = State::Idle;
+            // Without the end-of-stream frame, the stream ends with the
+            // transport between two chunks.
+            if clean && !self.codec.end_of_stream() {
+                return Poll::Ready(Ok(()));
+            }
+            // The transport ended before the peer finished the stream.
+            return Poll::Ready(Err(ChunkIOError::Disconnected));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// The payload of the chunk whose header was returned last.
+    pub fn payload(&mut self) -> Payload<'_, R> {
+        Payload { reader: self }
+    }
+}
+
+/// Payload of a chunk from a [`StreamingReader`], as a stream of pieces or
+/// an `AsyncRead`.
+pub struct Payload<'a, R> {
+    reader: &'a mut StreamingReader<R>,
+}
+
+impl<R> Stream for Payload<'_, R>
+where
+    R: AsyncRead + Unpin,
+{
+    type Item = Result<Bytes, ChunkIOError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.reader.poll_piece(cx).map(Result::transpose)
+    }
+}
+
+impl<R> AsyncRead for Payload<'_, R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let reader = &mut *self.reader;
+        if let Some(mut piece) = ready!(reader.poll_piece(cx))? {
+            let len = piece.len().min(buf.remaining());
+            buf.put_slice(&piece[..len]);
+            piece.advance(len);
+            reader.piece = piece;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
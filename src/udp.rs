@@ -0,0 +1,635 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures::{Future, Sink, Stream};
+use tokio::{
+    io::ReadBuf,
+    net::UdpSocket,
+    time::{sleep_until, Instant, Sleep},
+};
+use tokio_util::bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    chunk::Chunk,
+    control::ControlFrame,
+    fragment::{Defragmenter, Fragment},
+    proto::{ChunkIOProto, RawFrame},
+    reorder::Reassembly,
+    ChunkIOBuilder, ChunkIOError,
+};
+
+/// Datagram size that fits the path MTU of practically every network.
+pub const DEFAULT_DATAGRAM_SIZE: usize = 1200;
+/// Unacknowledged bytes in flight before the sink applies backpressure.
+pub const DEFAULT_UDP_WINDOW: usize = 256 * 1024;
+
+// Datagram flags: an ack section follows the flags byte, then any frames.
+const FLAG_ACK: u8 = 0x1;
+// The sender has received the peer's close.
+const FLAG_CLOSED: u8 = 0x2;
+const MAX_SACK_RANGES: usize = 16;
+const MAX_DATAGRAM_SIZE: usize = 64 * 1024;
+
+const INITIAL_RTO: Duration = Duration::from_millis(200);
+const MIN_RTO: Duration = Duration::from_millis(50);
+const MAX_RTO: Duration = Duration::from_secs(10);
+const MAX_RETRANSMITS: u32 = 12;
+// A peer that saw the close may already be gone, so its confirmation is only
+// waited for this many retransmissions; all data was acknowledged by then.
+const MAX_CLOSE_RETRANSMITS: u32 = 4;
+// Acks of later data after which a chunk is presumed lost without waiting
+// for its timeout.
+const FAST_RETRANSMIT_THRESHOLD: u32 = 3;
+const INITIAL_CWND_DATAGRAMS: usize = 10;
+const MIN_CWND_DATAGRAMS: usize = 2;
+
+struct Sent {
+    frame: Bytes,
+    len: u64,
+    sent_at: Option<Instant>,
+    retransmits: u32,
+    overtaken: u32,
+}
+
+/// A reliable, ordered chunk stream over a connected UDP socket.
+///
+/// Chunks keep their byte offsets; each datagram carries as many as fit
+/// after an optional acknowledgement of the peer's data, made of the
+/// cumulative receive offset and up to 16 ranges received beyond it. A chunk
+/// that later chunks overtook three times is sent again; once one goes
+/// unacknowledged for the retransmission timeout, everything in flight is
+/// sent again. The amount in flight follows
+/// a congestion window that grows with acks and shrinks on loss.
+/// Retransmission is driven by polling the stream or the sink, and every
+/// chunk has to fit in one datagram; with a
+/// [fragment size](ChunkIOBuilder::fragment_size) that fits, larger messages
+/// go out as several chunks. Chunks are told apart by their offsets,
+/// so empty ones cannot be sent. Closing the sink ends the peer's stream
+/// once all data was acknowledged.
+pub struct UdpChunkIO<I = Vec<u8>> {
+    socket: UdpSocket,
+    codec: ChunkIOProto,
+    datagram_size: usize,
+    unsent: VecDeque<u64>,
+    unacked: BTreeMap<u64, Sent>,
+    unacked_len: usize,
+    window: usize,
+    in_flight: usize,
+    cwnd: usize,
+    ssthresh: usize,
+    srtt: Option<Duration>,
+    rto: Duration,
+    timer: Pin<Box<Sleep>>,
+    reassembly: Reassembly,
+    // Byte ranges of the chunks held in `reassembly`, for selective acks.
+    held: BTreeMap<u64, u64>,
+    message: Defragmenter,
+    receive_capacity: usize,
+    receive_index: u64,
+    inbound: VecDeque<Chunk>,
+    inbound_len: usize,
+    ack_pending: bool,
+    recv_buf: Vec<u8>,
+    closing: bool,
+    close_sent_at: Option<Instant>,
+    close_retransmits: u32,
+    close_acked: bool,
+    remote_closed: bool,
+    // Set once received data broke a limit; nothing after it is delivered.
+    failed: bool,
+    error: Option<ChunkIOError>,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+    _item: PhantomData<fn(I) -> I>,
+}
+
+// ICMP errors reported for earlier datagrams, e.g. while the peer's socket is
+// not bound yet, say nothing about later ones; missing acks do.
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset
+    )
+}
+
+impl<I> UdpChunkIO<I> {
+    /// Wraps a socket that is already connected to the peer.
+    pub fn new(socket: UdpSocket, builder: &ChunkIOBuilder) -> UdpChunkIO<I> {
+        UdpChunkIO::with_datagram_size(socket, builder, DEFAULT_DATAGRAM_SIZE)
+    }
+
+    pub fn with_datagram_size(
+        socket: UdpSocket,
+        builder: &ChunkIOBuilder,
+        datagram_size: usize,
+    ) -> UdpChunkIO<I> {
+        let receive_capacity = builder.reorder_capacity.unwrap_or(DEFAULT_UDP_WINDOW);
+        let datagram_size = datagram_size.min(MAX_DATAGRAM_SIZE);
+        UdpChunkIO {
+            socket,
+            codec: builder.codec(),
+            datagram_size,
+            unsent: VecDeque::new(),
+            unacked: BTreeMap::new(),
+            unacked_len: 0,
+            window: DEFAULT_UDP_WINDOW,
+            in_flight: 0,
+            cwnd: INITIAL_CWND_DATAGRAMS * datagram_size,
+            ssthresh: usize::MAX,
+            srtt: None,
+            rto: INITIAL_RTO,
+            timer: Box::pin(sleep_until(Instant::now())),
+            reassembly: Reassembly::new(receive_capacity),
+            held: BTreeMap::new(),
+            message: Defragmenter::new(builder.max_message_length),
+            receive_capacity,
+            receive_index: 0,
+            inbound: VecDeque::new(),
+            inbound_len: 0,
+            ack_pending: false,
+            recv_buf: vec![0; MAX_DATAGRAM_SIZE],
+            closing: false,
+            close_sent_at: None,
+            close_retransmits: 0,
+            close_acked: false,
+            remote_closed: false,
+            failed: false,
+            error: None,
+            read_waker: None,
+            write_waker: None,
+            _item: PhantomData,
+        }
+    }
+
+    /// Returns the send and receive byte offsets.
+    pub fn current_index(&self) -> (u64, u64) {
+        (self.codec.current_index().0, self.receive_index)
+    }
+
+    /// Smoothed round-trip time measured from acknowledgements.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Receives, retransmits and sends whatever is ready without blocking.
+    fn poll_io(&mut self, cx: &mut Context) -> Result<(), ChunkIOError> {
+        self.poll_receive(cx)?;
+        self.poll_timer(cx)?;
+        self.poll_transmit(cx)
+    }
+
+    fn poll_receive(&mut self, cx: &mut Context) -> Result<(), ChunkIOError> {
+        loop {
+            let mut buf = ReadBuf::new(&mut self.recv_buf);
+            match self.socket.poll_recv(cx, &mut buf) {
+                Poll::Ready(Err(e)) if is_transient(&e) => continue,
+                Poll::Ready(result) => result?,
+                Poll::Pending => return Ok(()),
+            }
+            let datagram = Bytes::copy_from_slice(buf.filled());
+            // A malformed or corrupted datagram is dropped like a lost one.
+            let _ = self.on_datagram(datagram);
+        }
+    }
+
+    fn on_datagram(&mut self, mut datagram: Bytes) -> Result<(), ChunkIOError> {
+        if !datagram.has_remaining() {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        let flags = datagram.get_u8();
+        if flags & FLAG_CLOSED != 0 && self.closing {
+            self.close_acked = true;
+            if let Some(waker) = self.write_waker.take() {
+                waker.wake();
+            }
+        }
+        if flags & FLAG_ACK != 0 {
+            if datagram.remaining() < 9 {
+                return Err(ChunkIOError::InvalidChunk);
+            }
+            let cumulative = datagram.get_u64();
+            let count = datagram.get_u8() as usize;
+            if datagram.remaining() < count * 16 {
+                return Err(ChunkIOError::InvalidChunk);
+            }
+            let ranges = (0..count)
+                .map(|_| (datagram.get_u64(), datagram.get_u64()))
+                .collect::<Vec<_>>();
+            self.on_ack(cumulative, &ranges);
+        }
+        let mut frames = BytesMut::from(&datagram[..]);
+        while let Some(frame) = self.codec.decode_raw(&mut frames, None)? {
+            match frame {
+                RawFrame::Data { index, fragment } => self.on_chunk(index, fragment),
+                // The close carries the peer's final offset and only counts
+                // once everything before it has arrived.
+                RawFrame::Control(ControlFrame::Close(index)) => {
+                    if index != self.receive_index {
+                        continue;
+                    }
+                    self.remote_closed = true;
+                    self.ack_pending = true;
+                    if let Some(waker) = self.read_waker.take() {
+                        waker.wake();
+                    }
+                }
+                RawFrame::Control(_) => {}
+                RawFrame::End { .. } => return Err(ChunkIOError::InvalidChunk),
+            }
+        }
+        // A datagram carries whole frames; the rest of a cut off one is not
+        // coming.
+        self.codec.discard_partial();
+        Ok(())
+    }
+
+    fn on_ack(&mut self, cumulative: u64, ranges: &[(u64, u64)]) {
+        let now = Instant::now();
+        let acked = self
+            .unacked
+            .iter()
+            .filter(|(offset, sent)| {
+                let end = *offset + sent.len;
+                end <= cumulative
+                    || ranges
+                        .iter()
+                        .any(|(start, stop)| *start <= **offset && end <= *stop)
+            })
+            .map(|(offset, _)| *offset)
+            .collect::<Vec<_>>();
+        let mut acked_end = None;
+        for offset in acked {
+            let sent = self.unacked.remove(&offset).unwrap();
+            self.unacked_len -= sent.frame.len();
+            self.unsent.retain(|unsent| *unsent != offset);
+            let Some(sent_at) = sent.sent_at else {
+                continue;
+            };
+            self.in_flight -= sent.frame.len();
+            self.cwnd += match self.cwnd < self.ssthresh {
+                true => sent.frame.len(),
+                false => (self.datagram_size * sent.frame.len() / self.cwnd).max(1),
+            };
+            acked_end = acked_end.max(Some(offset + sent.len));
+            // Only chunks sent once give an unambiguous sample.
+            if sent.retransmits == 0 {
+                self.on_rtt_sample(now - sent_at);
+            }
+        }
+        let mut lost = false;
+        for (offset, sent) in self.unacked.iter_mut() {
+            if sent.sent_at.is_none() || acked_end.is_none_or(|end| end <= *offset) {
+                continue;
+            }
+            sent.overtaken += 1;
+            if sent.overtaken == FAST_RETRANSMIT_THRESHOLD {
+                self.in_flight -= sent.frame.len();
+                sent.sent_at = None;
+                sent.retransmits += 1;
+                sent.overtaken = 0;
+                self.unsent.push_back(*offset);
+                lost = true;
+            }
+        }
+        if lost {
+            self.ssthresh = (self.cwnd / 2).max(MIN_CWND_DATAGRAMS * self.datagram_size);
+            self.cwnd = self.ssthresh;
+            self.unsent.make_contiguous().sort_unstable();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn on_rtt_sample(&mut self, rtt: Duration) {
+        let srtt = match self.srtt {
+            Some(srtt) => (srtt * 7 + rtt) / 8,
+            None => rtt,
+        };
+        self.srtt = Some(srtt);
+        self.rto = (srtt * 2).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    fn on_chunk(&mut self, index: u64, fragment: Fragment) {
+        if self.failed {
+            return;
+        }
+        self.ack_pending = true;
+        if index >= self.receive_index
+            && self.inbound_len + fragment.body.len() > self.receive_capacity
+        {
+            // The application is not keeping up; the peer retransmits later.
+            return;
+        }
+        let end = index + fragment.body.len() as u64;
+        let mut fragment = match self.reassembly.insert(self.receive_index, index, fragment) {
+            Ok(Some(fragment)) => fragment,
+            Ok(None) => {
+                if index > self.receive_index {
+                    self.held.insert(index, end);
+                }
+                return;
+            }
+            Err(_) => return,
+        };
+        loop {
+            let len = fragment.body.len() as u64;
+            // A partial message is bounded by the maximum message length
+            // rather than the receive capacity, which it may exceed.
+            match self.message.push(fragment) {
+                Ok(Some(chunk)) => {
+                    self.inbound_len += chunk.stream_len();
+                    self.inbound.push_back(chunk);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    self.failed = true;
+                    self.error = Some(e);
+                    break;
+                }
+            }
+            self.receive_index += len;
+            match self.reassembly.pop(self.receive_index) {
+                Ok(Some(next)) => fragment = next,
+                _ => break,
+            }
+        }
+        self.held = self.held.split_off(&self.receive_index);
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Requeues every chunk whose retransmission timeout expired.
+    fn poll_timer(&mut self, cx: &mut Context) -> Result<(), ChunkIOError> {
+        loop {
+            let now = Instant::now();
+            let mut expired = false;
+            let mut deadline = None::<Instant>;
+            for sent in self.unacked.values() {
+                let Some(sent_at) = sent.sent_at else {
+                    continue;
+                };
+                if sent_at + self.rto <= now {
+                    if sent.retransmits >= MAX_RETRANSMITS {
+                        return Err(ChunkIOError::Timeout);
+                    }
+                    expired = true;
+                } else {
+                    let at = sent_at + self.rto;
+                    deadline = Some(deadline.map_or(at, |deadline| deadline.min(at)));
+                }
+            }
+            if expired {
+                // Past a burst of losses the acks cannot name everything that
+                // arrived, so the whole flight is sent again from the oldest
+                // chunk, which the peer is still waiting for.
+                deadline = None;
+                for (offset, sent) in self.unacked.iter_mut() {
+                    if sent.sent_at.take().is_some() {
+                        sent.retransmits += 1;
+                        sent.overtaken = 0;
+                        self.unsent.push_back(*offset);
+                    }
+                }
+                self.in_flight = 0;
+            }
+            if let (false, Some(sent_at)) = (self.close_acked, self.close_sent_at) {
+                if sent_at + self.rto <= now {
+                    if self.close_retransmits == MAX_CLOSE_RETRANSMITS {
+                        self.close_acked = true;
+                        if let Some(waker) = self.write_waker.take() {
+                            waker.wake();
+                        }
+                    } else {
+                        self.close_sent_at = None;
+                        self.close_retransmits += 1;
+                        expired = true;
+                    }
+                } else {
+                    let at = sent_at + self.rto;
+                    deadline = Some(deadline.map_or(at, |deadline| deadline.min(at)));
+                }
+            }
+            if expired {
+                self.rto = (self.rto * 2).min(MAX_RTO);
+                self.ssthresh = (self.cwnd / 2).max(MIN_CWND_DATAGRAMS * self.datagram_size);
+                self.cwnd = MIN_CWND_DATAGRAMS * self.datagram_size;
+                self.unsent.make_contiguous().sort_unstable();
+            }
+            let Some(deadline) = deadline else {
+                return Ok(());
+            };
+            self.timer.as_mut().reset(deadline);
+            if self.timer.as_mut().poll(cx).is_pending() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Whether the close should go out: once everything before it was
+    /// acknowledged, and again whenever its timeout expires.
+    fn close_due(&self) -> bool {
+        self.closing && !self.close_acked && self.close_sent_at.is_none() && self.unacked.is_empty()
+    }
+
+    /// Whether `len` more bytes may go out now; something is always allowed
+    /// when nothing is in flight.
+    fn fits_cwnd(&self, len: usize) -> bool {
+        self.in_flight == 0 || self.in_flight + len <= self.cwnd
+    }
+
+    /// Packs the pending ack and unsent chunks into datagrams.
+    fn poll_transmit(&mut self, cx: &mut Context) -> Result<(), ChunkIOError> {
+        while self.ack_pending
+            || self.close_due()
+            || self
+                .unsent
+                .front()
+                .is_some_and(|offset| self.fits_cwnd(self.unacked[offset].frame.len()))
+        {
+            let mut datagram = BytesMut::with_capacity(self.datagram_size);
+            if self.ack_pending {
+                datagram.put_u8(match self.remote_closed {
+                    true => FLAG_ACK | FLAG_CLOSED,
+                    false => FLAG_ACK,
+                });
+                datagram.put_u64(self.receive_index);
+                let mut ranges = Vec::<(u64, u64)>::new();
+                for (&start, &end) in self.held.iter() {
+                    if let Some(last) = ranges.last_mut().filter(|last| last.1 == start) {
+                        last.1 = end;
+                    } else if ranges.len() == MAX_SACK_RANGES {
+                        break;
+                    } else {
+                        ranges.push((start, end));
+                    }
+                }
+                datagram.put_u8(ranges.len() as u8);
+                for (start, end) in ranges {
+                    datagram.put_u64(start);
+                    datagram.put_u64(end);
+                }
+            } else {
+                datagram.put_u8(0);
+            }
+            let mut packed = 0;
+            let mut packed_len = 0;
+            for offset in self.unsent.iter() {
+                let frame = &self.unacked[offset].frame;
+                if datagram.len() + frame.len() > self.datagram_size
+                    || !self.fits_cwnd(packed_len + frame.len())
+                {
+                    break;
+                }
+                datagram.extend_from_slice(frame);
+                packed += 1;
+                packed_len += frame.len();
+            }
+            let close = self.close_due();
+            if close {
+                self.codec.encode_control(
+                    ControlFrame::Close(self.codec.current_index().0),
+                    &mut datagram,
+                );
+            }
+            match self.socket.poll_send(cx, &datagram) {
+                // Counts as sent and lost.
+                Poll::Ready(Err(e)) if is_transient(&e) => {}
+                Poll::Ready(result) => {
+                    result?;
+                }
+                Poll::Pending => return Ok(()),
+            }
+            self.ack_pending = false;
+            self.in_flight += packed_len;
+            let now = Instant::now();
+            if close {
+                self.close_sent_at = Some(now);
+            }
+            for offset in self.unsent.drain(..packed) {
+                if let Some(sent) = self.unacked.get_mut(&offset) {
+                    sent.sent_at = Some(now);
+                }
+            }
+        }
+        self.poll_timer(cx)
+    }
+}
+
+impl<I> Stream for UdpChunkIO<I>
+where
+    I: From<Chunk>,
+{
+    type Item = Result<I, ChunkIOError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        this.poll_io(cx)?;
+        if let Some(chunk) = this.inbound.pop_front() {
+            this.inbound_len -= chunk.stream_len();
+            return Poll::Ready(Some(Ok(I::from(chunk))));
+        }
+        if let Some(e) = this.error.take() {
+            return Poll::Ready(Some(Err(e)));
+        }
+        if this.remote_closed || this.failed {
+            return Poll::Ready(None);
+        }
+        this.read_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<I> Sink<I> for UdpChunkIO<I>
+where
+    I: Into<Chunk>,
+{
+    type Error = ChunkIOError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = &mut *self;
+        this.poll_io(cx)?;
+        if this.unacked_len >= this.window {
+            this.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        let this = &mut *self;
+        let chunk: Chunk = item.into();
+        if chunk.stream_len() == 0 {
+            return Err(ChunkIOError::InvalidChunk);
+        }
+        let (start, receive_index) = this.codec.current_index();
+        let mut offset = start;
+        let mut frames = Vec::new();
+        for (flags, fragment) in this.codec.fragments(chunk)? {
+            let len = fragment.len() as u64;
+            let mut frame = BytesMut::new();
+            this.codec
+                .encode_fragment_copied(offset, flags, &fragment, &mut frame)?;
+            // One byte for the datagram flags.
+            if frame.len() + 1 > this.datagram_size {
+                let overhead = (frame.len() as u64).saturating_sub(len);
+                return Err(ChunkIOError::ChunkTooLarge {
+                    length: len,
+                    max: (this.datagram_size as u64 - 1).saturating_sub(overhead),
+                });
+            }
+            frames.push((offset, len, frame));
+            offset += len;
+        }
+        this.codec.set_current_index((offset, receive_index));
+        for (offset, len, frame) in frames {
+            this.unacked_len += frame.len();
+            this.unacked.insert(
+                offset,
+                Sent {
+                    frame: frame.freeze(),
+                    len,
+                    sent_at: None,
+                    retransmits: 0,
+                    overtaken: 0,
+                },
+            );
+            this.unsent.push_back(offset);
+        }
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = &mut *self;
+        this.poll_io(cx)?;
+        if !this.unsent.is_empty() {
+            this.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Waits until the peer acknowledged everything sent, then tells it the
+    /// stream is over and waits for it to confirm.
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = &mut *self;
+        this.closing = true;
+        this.poll_io(cx)?;
+        if !this.close_acked {
+            this.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    collections::VecDeque,
+    io::IoSlice,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::ready;
+use tokio::io::AsyncWrite;
+use tokio_util::{
+    bytes::{Buf, Bytes, BytesMut},
+    io::poll_write_buf,
+};
+
+use crate::error::ChunkIOError;
+
+// Payloads shorter than this are cheaper to copy next to their header than to
+// queue as a separate segment.
+const COPY_THRESHOLD: usize = 4 * 1024;
+
+/// Outgoing bytes: headers and small payloads are coalesced into `tail`,
+/// larger payloads are queued as they are and written with vectored I/O.
+pub(crate) struct WriteBuf {
+    queued: VecDeque<Bytes>,
+    queued_len: usize,
+    tail: BytesMut,
+}
+
+impl WriteBuf {
+    pub(crate) fn with_capacity(capacity: usize) -> WriteBuf {
+        WriteBuf {
+            queued: VecDeque::new(),
+            queued_len: 0,
+            tail: BytesMut::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn tail_mut(&mut self) -> &mut BytesMut {
+        &mut self.tail
+    }
+
+    pub(crate) fn push(&mut self, payload: Bytes) {
+        if payload.len() < COPY_THRESHOLD {
+            self.tail.extend_from_slice(&payload);
+            return;
+        }
+        if !self.tail.is_empty() {
+            let head = self.tail.split().freeze();
+            self.queued_len += head.len();
+            self.queued.push_back(head);
+        }
+        self.queued_len += payload.len();
+        self.queued.push_back(payload);
+    }
+
+    /// Writes everything buffered to `io`, without flushing it.
+    pub(crate) fn poll_write_to<T>(
+        &mut self,
+        io: &mut T,
+        cx: &mut Context,
+    ) -> Poll<Result<(), ChunkIOError>>
+    where
+        T: AsyncWrite + Unpin,
+    {
+        while self.has_remaining() {
+            let n = ready!(poll_write_buf(Pin::new(&mut *io), cx, self))?;
+            if n == 0 {
+                return Poll::Ready(Err(
+                    std::io::Error::from(std::io::ErrorKind::WriteZero).into()
+                ));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Buf for WriteBuf {
+    fn remaining(&self) -> usize {
+        self.queued_len + self.tail.len()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        match self.queued.front() {
+            Some(front) => front,
+            None => &self.tail,
+        }
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        while let Some(front) = self.queued.front_mut() {
+            if cnt < front.len() {
+                front.advance(cnt);
+                self.queued_len -= cnt;
+                return;
+            }
+            cnt -= front.len();
+            self.queued_len -= front.len();
+            self.queued.pop_front();
+        }
+        self.tail.advance(cnt);
+    }
+
+    fn chunks_vectored<'a>(&'a self, dst: &mut [IoSlice<'a>]) -> usize {
+        let segments = self
+            .queued
+            .iter()
+            .map(|b| &b[..])
+            .chain(std::iter::once(&self.tail[..]))
+            .filter(|b| !b.is_empty());
+        let mut n = 0;
+        for (slot, segment) in dst.iter_mut().zip(segments) {
+            *slot = IoSlice::new(segment);
+            n += 1;
+        }
+        n
+    }
+}
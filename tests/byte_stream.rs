@@ -0,0 +1,72 @@
+use std::io::{Cursor, ErrorKind};
+
+use chunkio::{ByteStream, ChunkIO, ChunkIOBuilder, ChunkIOError};
+use futures::StreamExt;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio_util::bytes::Bytes;
+
+fn data() -> Vec<u8> {
+    (0..50_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn writes_become_bounded_chunks() {
+    let (a, b) = tokio::io::duplex(1 << 20);
+    let mut writer: ByteStream<_> = ByteStream::with_write_chunk_size(ChunkIO::new(a), 4096);
+    writer.write_all(&data()).await.unwrap();
+    writer.shutdown().await.unwrap();
+
+    let mut receiver = ChunkIO::new(b);
+    let mut received = Vec::new();
+    while let Some(chunk) = receiver.next().await {
+        let chunk = chunk.unwrap();
+        assert!(chunk.len() <= 4096);
+        received.extend_from_slice(&chunk);
+    }
+    assert_eq!(received, data());
+}
+
+#[tokio::test]
+async fn reads_across_chunk_boundaries() {
+    let (a, b) = tokio::io::duplex(1 << 20);
+    let mut writer: ByteStream<_> = ByteStream::with_write_chunk_size(ChunkIO::new(a), 1000);
+    let mut reader: ByteStream<_, Bytes> = ByteStream::new(ChunkIOBuilder::new().build_bytes(b));
+    writer.write_all(&data()).await.unwrap();
+    writer.shutdown().await.unwrap();
+
+    let mut head = [0; 1500];
+    reader.read_exact(&mut head).await.unwrap();
+    assert_eq!(head[..], data()[..1500]);
+    assert_eq!(reader.fill_buf().await.unwrap(), &data()[1500..2000]);
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(rest, data()[1500..]);
+}
+
+#[tokio::test]
+async fn shutdown_ends_the_peer_stream() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let mut writer: ByteStream<_> = ByteStream::new(ChunkIO::new(a));
+    let mut reader: ByteStream<_> = ByteStream::new(ChunkIO::new(b));
+    writer.write_all(b"hello").await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut received = String::new();
+    reader.read_to_string(&mut received).await.unwrap();
+    assert_eq!(received, "hello");
+    assert_eq!(reader.read(&mut [0; 8]).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn stream_errors_become_io_errors() {
+    let wire = vec![0xe9];
+    let io = tokio::io::join(Cursor::new(wire), tokio::io::sink());
+    let mut reader: ByteStream<_> = ByteStream::new(ChunkIO::new(io));
+    let error = reader.read(&mut [0; 8]).await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Other);
+    let error = error
+        .into_inner()
+        .unwrap()
+        .downcast::<ChunkIOError>()
+        .unwrap();
+    assert!(matches!(*error, ChunkIOError::InvalidChunk));
+}
@@ -0,0 +1,67 @@
+use chunkio::{ChunkIO, ChunkIOBuilder, Frame};
+use futures::{SinkExt, StreamExt};
+use tokio::io::AsyncReadExt;
+use tokio_util::{
+    bytes::{Bytes, BytesMut},
+    codec::Decoder,
+};
+
+// Small payloads are copied next to their header, large ones are written from
+// the caller's buffer; both kinds interleave here.
+fn payloads() -> Vec<Vec<u8>> {
+    (1..40u8)
+        .map(|i| vec![i; (i as usize * 997) % 20_000 + 1])
+        .collect()
+}
+
+#[tokio::test]
+async fn round_trips_bytes() {
+    let (a, b) = tokio::io::duplex(1 << 20);
+    let mut sender = ChunkIOBuilder::new().build_bytes(a);
+    let mut receiver = ChunkIOBuilder::new().build_bytes(b);
+    for payload in payloads() {
+        sender.feed(Bytes::from(payload)).await.unwrap();
+    }
+    sender.close().await.unwrap();
+    for payload in payloads() {
+        assert_eq!(receiver.next().await.unwrap().unwrap(), payload);
+    }
+    assert!(receiver.next().await.is_none());
+}
+
+#[tokio::test]
+async fn bytes_and_vec_senders_write_the_same_stream() {
+    let mut wires = Vec::new();
+    for bytes in [false, true] {
+        let (a, mut b) = tokio::io::duplex(1 << 20);
+        if bytes {
+            let mut sender = ChunkIOBuilder::new().build_bytes(a);
+            for payload in payloads() {
+                sender.feed(Bytes::from(payload)).await.unwrap();
+            }
+            sender.close().await.unwrap();
+        } else {
+            let mut sender = ChunkIO::new(a);
+            for payload in payloads() {
+                sender.feed(payload).await.unwrap();
+            }
+            sender.close().await.unwrap();
+        }
+        let mut wire = Vec::new();
+        b.read_to_end(&mut wire).await.unwrap();
+        wires.push(wire);
+    }
+    assert_eq!(wires[0], wires[1]);
+}
+
+#[test]
+fn decoded_chunks_share_the_read_buffer() {
+    let mut codec = ChunkIOBuilder::new().codec();
+    let mut src = BytesMut::from(&[0x01, 0x03, 1, 2, 3][..]);
+    let payload = src[2..].as_ptr();
+    let Some(Frame::Data(chunk)) = codec.decode(&mut src).unwrap() else {
+        panic!("expected a chunk");
+    };
+    assert_eq!(chunk.payload, [1, 2, 3][..]);
+    assert_eq!(chunk.payload.as_ptr(), payload);
+}
@@ -0,0 +1,109 @@
+#![cfg(any(feature = "crc32c", feature = "xxhash"))]
+
+use std::{future::poll_fn, io::Cursor};
+
+use chunkio::{Checksum, ChunkIO, ChunkIOBuilder, ChunkIOError, ControlFrame, StreamingReader};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, Join, Sink};
+
+fn checksums() -> Vec<Checksum> {
+    vec![
+        #[cfg(feature = "crc32c")]
+        Checksum::Crc32c,
+        #[cfg(feature = "xxhash")]
+        Checksum::Xxh3,
+    ]
+}
+
+// Returns the bytes written for the chunk "first" followed by a ping and the
+// end of the stream.
+async fn wire(checksum: Checksum) -> Vec<u8> {
+    let (io, mut wire) = tokio::io::duplex(1 << 16);
+    let mut sender = ChunkIOBuilder::new().checksum(checksum).build(io);
+    sender.send(b"first".to_vec()).await.unwrap();
+    sender.send_control(ControlFrame::Ping(7));
+    sender.close().await.unwrap();
+    drop(sender);
+    let mut buf = Vec::new();
+    wire.read_to_end(&mut buf).await.unwrap();
+    buf
+}
+
+// Reads `wire` and throws away the replies.
+fn receiver(checksum: Checksum, wire: Vec<u8>) -> ChunkIO<Join<Cursor<Vec<u8>>, Sink>> {
+    ChunkIOBuilder::new()
+        .checksum(checksum)
+        .build(tokio::io::join(Cursor::new(wire), tokio::io::sink()))
+}
+
+#[tokio::test]
+async fn accepts_intact_frames() {
+    for checksum in checksums() {
+        let wire = wire(checksum).await;
+        // Header, payload and trailer, then the ping and the end of the
+        // stream with theirs.
+        assert_eq!(wire.len(), 2 + 5 + 2 + 2 + 3 * checksum.trailer_len());
+        let mut receiver = receiver(checksum, wire);
+        assert_eq!(receiver.next().await.unwrap().unwrap(), b"first");
+        assert!(receiver.next().await.is_none());
+        assert_eq!(
+            poll_fn(|cx| receiver.poll_control(cx)).await,
+            ControlFrame::Ping(7)
+        );
+    }
+}
+
+#[tokio::test]
+async fn rejects_corrupted_payload() {
+    for checksum in checksums() {
+        let mut wire = wire(checksum).await;
+        wire[3] ^= 1;
+        let mut receiver = receiver(checksum, wire);
+        assert!(matches!(
+            receiver.next().await,
+            Some(Err(ChunkIOError::ChecksumMismatch { offset: 0 }))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn rejects_corrupted_control_frame() {
+    for checksum in checksums() {
+        let mut wire = wire(checksum).await;
+        let ping = 2 + 5 + checksum.trailer_len();
+        wire[ping + 1] ^= 1;
+        let mut receiver = receiver(checksum, wire);
+        assert_eq!(receiver.next().await.unwrap().unwrap(), b"first");
+        assert!(matches!(
+            receiver.next().await,
+            Some(Err(ChunkIOError::ChecksumMismatch { offset: 5 }))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn rejects_a_missing_checksum() {
+    for checksum in checksums() {
+        let wire = wire(Checksum::None).await;
+        let mut receiver = receiver(checksum, wire);
+        assert!(matches!(receiver.next().await, Some(Err(_))));
+    }
+}
+
+#[tokio::test]
+async fn streamed_payloads_are_verified_at_their_end() {
+    for checksum in checksums() {
+        let mut wire = wire(checksum).await;
+        wire[3] ^= 1;
+        let builder = ChunkIOBuilder::new().checksum(checksum);
+        let mut reader = StreamingReader::new(Cursor::new(wire), &builder);
+        reader.next_chunk().await.unwrap().unwrap();
+        let mut payload = reader.payload();
+        // The corrupted payload is handed out before the trailer is read.
+        assert_eq!(payload.next().await.unwrap().unwrap(), &b"fhrst"[..]);
+        assert!(matches!(
+            payload.next().await,
+            Some(Err(ChunkIOError::ChecksumMismatch { offset: 0 }))
+        ));
+    }
+}
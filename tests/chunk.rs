@@ -0,0 +1,89 @@
+use std::io::Cursor;
+
+use chunkio::{Chunk, ChunkIOBuilder, ChunkIOError};
+use futures::{SinkExt, StreamExt};
+use tokio_util::bytes::Bytes;
+
+#[tokio::test]
+async fn round_trips_kind_and_metadata() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let mut sender = ChunkIOBuilder::new().build_chunks(a);
+    let mut receiver = ChunkIOBuilder::new().build_chunks(b);
+    let chunk = Chunk::new(&b"payload"[..])
+        .with_kind(3)
+        .with_metadata("content-type", &b"text/plain"[..])
+        .with_metadata("id", &b"7"[..]);
+    sender.send(chunk.clone()).await.unwrap();
+    let received = receiver.next().await.unwrap().unwrap();
+    assert_eq!(received, chunk);
+    assert_eq!(received.get("id").unwrap(), &b"7"[..]);
+    assert_eq!(received.get("missing"), None);
+
+    // Kind, entry count, then each key and value with their lengths count
+    // towards the offsets.
+    let length = 3 + (3 + 12 + 10) + (3 + 2 + 1) + 7;
+    assert_eq!(sender.codec().current_index().0, length);
+    assert_eq!(receiver.codec().current_index().1, length);
+}
+
+#[tokio::test]
+async fn plain_chunks_are_compatible_with_byte_peers() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let mut chunks = ChunkIOBuilder::new().build_chunks(a);
+    let mut bytes = ChunkIOBuilder::new().build(b);
+    chunks.send(Chunk::new(&b"abc"[..])).await.unwrap();
+    assert_eq!(bytes.next().await.unwrap().unwrap(), b"abc");
+    bytes.send(b"def".to_vec()).await.unwrap();
+    assert_eq!(
+        chunks.next().await.unwrap().unwrap(),
+        Chunk::new(&b"def"[..])
+    );
+}
+
+#[tokio::test]
+async fn byte_peers_see_only_the_payload() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let mut chunks = ChunkIOBuilder::new().build_chunks(a);
+    let mut bytes = ChunkIOBuilder::new().build_bytes(b);
+    chunks
+        .send(
+            Chunk::new(&b"abc"[..])
+                .with_kind(1)
+                .with_metadata("k", &b"v"[..]),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        bytes.next().await.unwrap().unwrap(),
+        Bytes::from_static(b"abc")
+    );
+    assert_eq!(
+        bytes.codec().current_index().1,
+        chunks.codec().current_index().0
+    );
+}
+
+#[tokio::test]
+async fn rejects_oversized_metadata() {
+    let (a, _b) = tokio::io::duplex(1 << 16);
+    let mut sender = ChunkIOBuilder::new().build_chunks(a);
+    let chunk = Chunk::new(&b""[..]).with_metadata("k".repeat(256), &b""[..]);
+    assert!(matches!(
+        sender.send(chunk).await,
+        Err(ChunkIOError::MetadataTooLarge)
+    ));
+    assert_eq!(sender.codec().current_index().0, 0);
+}
+
+#[tokio::test]
+async fn rejects_a_truncated_metadata_section() {
+    // An extended header flagging metadata, then kind 1 and one entry whose
+    // key runs past the chunk.
+    let wire = vec![0xf2, 0x01, 0x05, 0x00, 0x01, 0x01, 0x09, b'k'];
+    let mut receiver =
+        ChunkIOBuilder::new().build_chunks(tokio::io::join(Cursor::new(wire), tokio::io::sink()));
+    assert!(matches!(
+        receiver.next().await,
+        Some(Err(ChunkIOError::InvalidChunk))
+    ));
+}
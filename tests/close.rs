@@ -0,0 +1,74 @@
+use std::io::Cursor;
+
+use chunkio::{ChunkIO, ChunkIOBuilder, ChunkIOError};
+use futures::{SinkExt, StreamExt};
+
+#[tokio::test]
+async fn close_write_keeps_the_stream_readable() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let mut a = ChunkIO::new(a);
+    let mut b = ChunkIO::new(b);
+    a.send(b"request".to_vec()).await.unwrap();
+    a.close_write().await.unwrap();
+    assert!(matches!(
+        a.send(b"more".to_vec()).await,
+        Err(ChunkIOError::StreamClosed)
+    ));
+
+    assert_eq!(b.next().await.unwrap().unwrap(), b"request");
+    assert!(b.next().await.is_none());
+    b.send(b"response".to_vec()).await.unwrap();
+    b.close().await.unwrap();
+
+    assert_eq!(a.next().await.unwrap().unwrap(), b"response");
+    assert!(a.next().await.is_none());
+}
+
+#[tokio::test]
+async fn a_transport_ending_early_is_a_disconnect() {
+    // A chunk without the end-of-stream frame after it.
+    let wire = vec![0x01, 0x01, b'a'];
+    let mut receiver = ChunkIO::new(tokio::io::join(Cursor::new(wire), tokio::io::sink()));
+    assert_eq!(receiver.next().await.unwrap().unwrap(), b"a");
+    assert!(matches!(
+        receiver.next().await,
+        Some(Err(ChunkIOError::Disconnected))
+    ));
+    assert!(receiver.next().await.is_none());
+}
+
+#[tokio::test]
+async fn without_the_end_frame_close_write_shuts_down_the_writer() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let builder = ChunkIOBuilder::new().end_of_stream(false);
+    let mut a = builder.build(a);
+    let mut b = builder.build(b);
+    a.send(b"request".to_vec()).await.unwrap();
+    a.close_write().await.unwrap();
+
+    // The end of the transport ends the stream cleanly.
+    assert_eq!(b.next().await.unwrap().unwrap(), b"request");
+    assert!(b.next().await.is_none());
+    b.send(b"response".to_vec()).await.unwrap();
+    b.close().await.unwrap();
+
+    assert_eq!(a.next().await.unwrap().unwrap(), b"response");
+    assert!(a.next().await.is_none());
+}
+
+#[tokio::test]
+async fn a_writer_half_closes_while_its_reader_reads() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let (mut reader, mut writer) = ChunkIO::new(a).into_split();
+    let mut b = ChunkIO::new(b);
+    writer.send(b"request".to_vec()).await.unwrap();
+    writer.close_write().await.unwrap();
+
+    assert_eq!(b.next().await.unwrap().unwrap(), b"request");
+    assert!(b.next().await.is_none());
+    b.send(b"response".to_vec()).await.unwrap();
+    b.close().await.unwrap();
+
+    assert_eq!(reader.next().await.unwrap().unwrap(), b"response");
+    assert!(reader.next().await.is_none());
+}
@@ -0,0 +1,204 @@
+use chunkio::{Chunk, ChunkIOBuilder, ChunkIOError, Frame};
+use futures::{SinkExt, StreamExt};
+use tokio_util::{
+    bytes::{Bytes, BytesMut},
+    codec::{Decoder, Encoder},
+};
+
+#[tokio::test]
+async fn round_trips_chunks() {
+    let (a, b) = tokio::io::duplex(1 << 20);
+    let builder = ChunkIOBuilder::new().max_chunk_length(70_000);
+    let mut sender = builder.build(a);
+    let mut receiver = builder.build(b);
+    for chunk in [vec![1], vec![2; 300], vec![3; 70_000]] {
+        sender.send(chunk.clone()).await.unwrap();
+        assert_eq!(receiver.next().await.unwrap().unwrap(), chunk);
+    }
+    assert_eq!(sender.codec().current_index().0, 70_301);
+    assert_eq!(receiver.codec().current_index().1, 70_301);
+}
+
+#[test]
+fn encoder_rejects_chunks_over_the_limit() {
+    let mut codec = ChunkIOBuilder::new().max_chunk_length(4).codec();
+    let mut dst = BytesMut::new();
+    assert!(matches!(
+        codec.encode(vec![0; 5], &mut dst),
+        Err(ChunkIOError::ChunkTooLarge { length: 5, max: 4 })
+    ));
+    assert!(dst.is_empty());
+}
+
+#[test]
+fn decoder_rejects_declared_length_over_the_limit() {
+    let mut codec = ChunkIOBuilder::new().max_chunk_length(4).codec();
+    // Index 0 and a declared length of 5, before any payload arrives.
+    let mut src = BytesMut::from(&[0x01, 0x05][..]);
+    assert!(matches!(
+        codec.decode(&mut src),
+        Err(ChunkIOError::ChunkTooLarge { length: 5, max: 4 })
+    ));
+}
+
+#[test]
+fn decoder_rejects_lengths_that_overflow_the_frame() {
+    let mut codec = ChunkIOBuilder::new().max_chunk_length(u64::MAX).codec();
+    let mut src = BytesMut::from(&[0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff][..]);
+    assert!(matches!(
+        codec.decode(&mut src),
+        Err(ChunkIOError::ChunkTooLarge {
+            length: u64::MAX,
+            max: u64::MAX
+        })
+    ));
+}
+
+#[test]
+fn strict_decoding_rejects_padded_fields() {
+    // A one-byte chunk whose length field is padded to two bytes.
+    let padded = [0x02, 0x00, 0x01, 0xaa];
+
+    let mut codec = ChunkIOBuilder::new().codec();
+    let mut src = BytesMut::from(&padded[..]);
+    assert_eq!(
+        codec.decode(&mut src).unwrap(),
+        Some(Frame::Data(Chunk::new(Bytes::from_static(&[0xaa]))))
+    );
+
+    let mut codec = ChunkIOBuilder::new().strict(true).codec();
+    let mut src = BytesMut::from(&padded[..]);
+    assert!(matches!(
+        codec.decode(&mut src),
+        Err(ChunkIOError::InvalidChunk)
+    ));
+}
+
+#[tokio::test]
+async fn round_trips_empty_chunks() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let mut sender = ChunkIOBuilder::new().build(a);
+    let mut receiver = ChunkIOBuilder::new().build(b);
+    for chunk in [vec![], vec![1, 2], vec![], vec![]] {
+        sender.send(chunk.clone()).await.unwrap();
+        assert_eq!(receiver.next().await.unwrap().unwrap(), chunk);
+    }
+    assert_eq!(receiver.codec().current_index().1, 2);
+}
+
+#[tokio::test]
+async fn closing_sends_the_end_of_the_stream() {
+    let mut wire = Vec::new();
+    let mut sender = ChunkIOBuilder::new().build(tokio::io::join(tokio::io::empty(), &mut wire));
+    sender.send(b"abc".to_vec()).await.unwrap();
+    sender.close().await.unwrap();
+    // The chunk, then the end-of-stream frame at offset 3.
+    assert_eq!(wire, [0x01, 0x03, b'a', b'b', b'c', 0xe1, 0x03]);
+
+    let mut codec = ChunkIOBuilder::new().codec();
+    let mut src = BytesMut::from(&wire[..]);
+    assert_eq!(
+        codec.decode(&mut src).unwrap(),
+        Some(Frame::Data(Chunk::new(Bytes::from_static(b"abc"))))
+    );
+    assert_eq!(codec.decode(&mut src).unwrap(), Some(Frame::End));
+}
+
+#[tokio::test]
+async fn the_end_of_the_stream_can_be_turned_off() {
+    let mut wire = Vec::new();
+    let builder = ChunkIOBuilder::new().end_of_stream(false);
+    let mut sender = builder.build(tokio::io::join(tokio::io::empty(), &mut wire));
+    assert!(!sender.codec().end_of_stream());
+    sender.send(b"abc".to_vec()).await.unwrap();
+    sender.close().await.unwrap();
+    assert_eq!(wire, [0x01, 0x03, b'a', b'b', b'c']);
+}
+
+#[tokio::test]
+async fn the_stream_ends_at_the_end_frame() {
+    // Anything after the end-of-stream frame is never read.
+    let wire = [0x01, 0x01, b'a', 0xe1, 0x01, 0x01, 0x01, b'b'];
+    let mut receiver = ChunkIOBuilder::new().build(tokio::io::join(&wire[..], tokio::io::sink()));
+    assert_eq!(receiver.next().await.unwrap().unwrap(), b"a");
+    assert!(receiver.next().await.is_none());
+}
+
+#[test]
+fn decoder_rejects_an_end_frame_before_the_receive_offset() {
+    let mut codec = ChunkIOBuilder::new().codec();
+    let mut src = BytesMut::from(&[0x01, 0x02, b'a', b'b', 0xe1, 0x01][..]);
+    codec.decode(&mut src).unwrap();
+    assert!(codec.decode(&mut src).is_err());
+}
+
+#[test]
+fn encoder_writes_minimal_headers() {
+    let mut codec = ChunkIOBuilder::new().codec();
+    let mut dst = BytesMut::new();
+    codec.encode(Vec::new(), &mut dst).unwrap();
+    assert_eq!(dst[..], [0x00]);
+
+    dst.clear();
+    codec.encode(vec![0; 300], &mut dst).unwrap();
+    assert_eq!(dst[..3], [0x02, 0x01, 0x2c]);
+    assert_eq!(dst.len(), 3 + 300);
+
+    // Index 300 takes two bytes, length 1 one.
+    dst.clear();
+    codec.encode(vec![7], &mut dst).unwrap();
+    assert_eq!(dst[..], [0x21, 0x01, 0x2c, 0x01, 7]);
+}
+
+fn chunks() -> Vec<Chunk> {
+    vec![
+        Chunk::new(Vec::new()),
+        Chunk::new(vec![1]),
+        Chunk::new(vec![2; 300]),
+        Chunk::new(vec![3; 70_000]),
+        Chunk::new(vec![4; 10])
+            .with_kind(7)
+            .with_metadata("key", "value"),
+    ]
+}
+
+#[test]
+fn decodes_headers_split_across_reads() {
+    let builder = ChunkIOBuilder::new();
+    let mut encoder = builder.codec();
+    let mut wire = BytesMut::new();
+    for chunk in chunks() {
+        encoder.encode(chunk, &mut wire).unwrap();
+    }
+
+    // Every read returns a single byte, so each header arrives in pieces.
+    let mut decoder = builder.codec();
+    let mut src = BytesMut::new();
+    let mut decoded = Vec::new();
+    for byte in wire {
+        src.extend_from_slice(&[byte]);
+        while let Some(frame) = decoder.decode(&mut src).unwrap() {
+            decoded.push(frame);
+        }
+    }
+    assert!(src.is_empty());
+    assert_eq!(
+        decoded,
+        chunks().into_iter().map(Frame::Data).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn reserves_a_read_buffer_at_a_time() {
+    let mut decoder = ChunkIOBuilder::new().read_buffer_capacity(4096).codec();
+    // A chunk with metadata that declares 8 MiB.
+    let mut src = BytesMut::from(&[0xf2, 0x03, 0x7f, 0xff, 0xff][..]);
+    assert!(decoder.decode(&mut src).unwrap().is_none());
+    assert!(src.capacity() < 64 * 1024);
+
+    for _ in 0..64 {
+        src.extend_from_slice(&[0; 4096]);
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+        assert!(src.capacity() <= 2 * (src.len() + 4096));
+    }
+}
@@ -0,0 +1,88 @@
+#![cfg(any(feature = "zstd", feature = "lz4", feature = "deflate"))]
+
+use std::io::Cursor;
+
+use chunkio::{ChunkIO, ChunkIOBuilder, ChunkIOError, Compression};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, Join, Sink};
+
+fn compressions() -> Vec<Compression> {
+    vec![
+        #[cfg(feature = "zstd")]
+        Compression::Zstd,
+        #[cfg(feature = "lz4")]
+        Compression::Lz4,
+        #[cfg(feature = "deflate")]
+        Compression::Deflate,
+    ]
+}
+
+// A payload that compresses well, and one that does not.
+fn payloads() -> Vec<Vec<u8>> {
+    let noise = (0..4096u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    vec![vec![7; 4096], noise]
+}
+
+async fn wire(compression: Compression, payloads: &[Vec<u8>]) -> Vec<u8> {
+    let (io, mut wire) = tokio::io::duplex(1 << 20);
+    let mut sender = ChunkIOBuilder::new().compression(compression).build(io);
+    for payload in payloads {
+        sender.feed(payload.clone()).await.unwrap();
+    }
+    sender.close().await.unwrap();
+    drop(sender);
+    let mut buf = Vec::new();
+    wire.read_to_end(&mut buf).await.unwrap();
+    buf
+}
+
+fn receiver(builder: ChunkIOBuilder, wire: Vec<u8>) -> ChunkIO<Join<Cursor<Vec<u8>>, Sink>> {
+    builder.build(tokio::io::join(Cursor::new(wire), tokio::io::sink()))
+}
+
+#[tokio::test]
+async fn round_trips_compressed_chunks() {
+    for compression in compressions() {
+        let wire = wire(compression, &payloads()).await;
+        // The first payload shrinks; the second goes out as it is.
+        assert!(wire.len() < 4096 + 4096 / 2);
+        let mut receiver = receiver(ChunkIOBuilder::new().compression(compression), wire);
+        for payload in payloads() {
+            assert_eq!(receiver.next().await.unwrap().unwrap(), payload);
+        }
+        assert!(receiver.next().await.is_none());
+        assert_eq!(receiver.codec().current_index().1, 8192);
+    }
+}
+
+#[tokio::test]
+async fn limits_the_decompressed_length() {
+    for compression in compressions() {
+        let wire = wire(compression, &payloads()[..1]).await;
+        let builder = ChunkIOBuilder::new()
+            .compression(compression)
+            .max_decompressed_length(4095);
+        let mut receiver = receiver(builder, wire);
+        assert!(matches!(
+            receiver.next().await,
+            Some(Err(ChunkIOError::DecompressedTooLarge {
+                offset: 0,
+                max: 4095
+            }))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn rejects_compressed_chunks_without_compression() {
+    for compression in compressions() {
+        let wire = wire(compression, &payloads()[..1]).await;
+        let mut receiver = receiver(ChunkIOBuilder::new(), wire);
+        assert!(matches!(
+            receiver.next().await,
+            Some(Err(ChunkIOError::InvalidChunk))
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+use std::future::poll_fn;
+
+use chunkio::{ChunkIO, ChunkIOError, ControlFrame};
+use futures::{SinkExt, StreamExt};
+
+#[tokio::test]
+async fn answers_pings_with_pongs() {
+    let (a, b) = tokio::io::duplex(8192);
+    let mut a = ChunkIO::new(a);
+    let mut b = ChunkIO::new(b);
+    a.send(vec![1, 2, 3]).await.unwrap();
+    a.send_control(ControlFrame::Ping(0));
+    a.send_control(ControlFrame::Ping(123_456_789));
+    a.send(vec![4]).await.unwrap();
+
+    assert_eq!(b.next().await.unwrap().unwrap(), vec![1, 2, 3]);
+    assert_eq!(b.next().await.unwrap().unwrap(), vec![4]);
+    assert_eq!(
+        poll_fn(|cx| b.poll_control(cx)).await,
+        ControlFrame::Ping(0)
+    );
+    assert_eq!(
+        poll_fn(|cx| b.poll_control(cx)).await,
+        ControlFrame::Ping(123_456_789)
+    );
+    // Control frames take no room in the byte stream.
+    assert_eq!(b.codec().current_index().1, 4);
+
+    b.send(vec![5]).await.unwrap();
+    assert_eq!(a.next().await.unwrap().unwrap(), vec![5]);
+    assert_eq!(
+        poll_fn(|cx| a.poll_control(cx)).await,
+        ControlFrame::Pong(0)
+    );
+    assert_eq!(
+        poll_fn(|cx| a.poll_control(cx)).await,
+        ControlFrame::Pong(123_456_789)
+    );
+}
+
+#[tokio::test]
+async fn close_is_passed_on_without_ending_the_stream() {
+    let (a, b) = tokio::io::duplex(8192);
+    let mut a = ChunkIO::new(a);
+    let mut b = ChunkIO::new(b);
+    a.send(vec![1]).await.unwrap();
+    a.send_control(ControlFrame::Close(7));
+    a.send(vec![2]).await.unwrap();
+    a.close().await.unwrap();
+
+    assert_eq!(b.next().await.unwrap().unwrap(), vec![1]);
+    assert_eq!(b.next().await.unwrap().unwrap(), vec![2]);
+    assert!(b.next().await.is_none());
+    assert_eq!(
+        poll_fn(|cx| b.poll_control(cx)).await,
+        ControlFrame::Close(7)
+    );
+}
+
+#[tokio::test]
+async fn reset_fails_the_stream() {
+    let (a, b) = tokio::io::duplex(8192);
+    let mut a = ChunkIO::new(a);
+    let mut b = ChunkIO::new(b);
+    a.send(vec![1]).await.unwrap();
+    a.send_control(ControlFrame::Reset(9));
+    a.flush().await.unwrap();
+
+    assert_eq!(b.next().await.unwrap().unwrap(), vec![1]);
+    assert!(matches!(b.next().await, Some(Err(ChunkIOError::Reset))));
+    assert_eq!(
+        poll_fn(|cx| b.poll_control(cx)).await,
+        ControlFrame::Reset(9)
+    );
+}
@@ -0,0 +1,138 @@
+#![cfg(feature = "chacha20poly1305")]
+
+use std::{future::poll_fn, io::Cursor};
+
+use chunkio::{
+    ChunkIO, ChunkIOBuilder, ChunkIOError, Cipher, ControlFrame, MultipathChunkIO, RoundRobin,
+};
+use futures::{FutureExt, SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, Join, Sink};
+
+fn builder(send_key: u8, recv_key: u8) -> ChunkIOBuilder {
+    ChunkIOBuilder::new().encryption(Cipher::ChaCha20Poly1305, [send_key; 32], [recv_key; 32])
+}
+
+// Returns the bytes written for the chunk "first", two pings and the end of
+// the stream. The chunk header is `01 15`, the ping headers `91 07` and
+// `91 08`, each followed by its sequence number and tag, and the end `e1 05`
+// followed by its tag.
+async fn sealed() -> Vec<u8> {
+    let (io, mut wire) = tokio::io::duplex(1 << 16);
+    let mut sender = builder(1, 2).build(io);
+    sender.send(b"first".to_vec()).await.unwrap();
+    sender.send_control(ControlFrame::Ping(7));
+    sender.send_control(ControlFrame::Ping(8));
+    sender.close().await.unwrap();
+    drop(sender);
+    let mut buf = Vec::new();
+    wire.read_to_end(&mut buf).await.unwrap();
+    buf
+}
+
+// Reads `wire` and throws away the replies.
+fn receiver(wire: Vec<u8>) -> ChunkIO<Join<Cursor<Vec<u8>>, Sink>> {
+    builder(2, 1).build(tokio::io::join(Cursor::new(wire), tokio::io::sink()))
+}
+
+const CHUNK_LEN: usize = 2 + 5 + 16;
+const PING_LEN: usize = 2 + 8 + 16;
+const END_LEN: usize = 2 + 16;
+
+#[tokio::test]
+async fn accepts_sealed_frames() {
+    let wire = sealed().await;
+    assert_eq!(wire.len(), CHUNK_LEN + 2 * PING_LEN + END_LEN);
+    let mut receiver = receiver(wire);
+    assert_eq!(receiver.next().await.unwrap().unwrap(), b"first");
+    assert!(receiver.next().await.is_none());
+    for ping in [7, 8] {
+        assert_eq!(
+            poll_fn(|cx| receiver.poll_control(cx)).await,
+            ControlFrame::Ping(ping)
+        );
+    }
+}
+
+#[tokio::test]
+async fn rejects_tampered_header() {
+    let wire = sealed().await;
+    // The same offset and length with a one-byte index: valid unsealed, but
+    // the header is authenticated as sent.
+    let mut tampered = vec![0x11, 0x00];
+    tampered.extend_from_slice(&wire[1..]);
+    let mut receiver = receiver(tampered);
+    assert!(matches!(
+        receiver.next().await,
+        Some(Err(ChunkIOError::AuthenticationFailed { offset: 0 }))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_tampered_tag() {
+    let mut wire = sealed().await;
+    wire[CHUNK_LEN - 1] ^= 1;
+    let mut receiver = receiver(wire);
+    assert!(matches!(
+        receiver.next().await,
+        Some(Err(ChunkIOError::AuthenticationFailed { offset: 0 }))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_tampered_control_frame() {
+    let mut wire = sealed().await;
+    wire[CHUNK_LEN + PING_LEN - 1] ^= 1;
+    let mut receiver = receiver(wire);
+    assert_eq!(receiver.next().await.unwrap().unwrap(), b"first");
+    assert!(matches!(
+        receiver.next().await,
+        Some(Err(ChunkIOError::AuthenticationFailed { offset: 5 }))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_replayed_control_frame() {
+    let wire = sealed().await;
+    let ping = &wire[CHUNK_LEN..CHUNK_LEN + PING_LEN];
+    let wire = [&wire[..CHUNK_LEN], ping, ping].concat();
+    let mut receiver = receiver(wire);
+    assert_eq!(receiver.next().await.unwrap().unwrap(), b"first");
+    assert!(matches!(
+        receiver.next().await,
+        Some(Err(ChunkIOError::AuthenticationFailed { .. }))
+    ));
+}
+
+#[tokio::test]
+async fn rejects_injected_close() {
+    let wire = sealed().await;
+    for injected in [&[0xb0][..], &[0xb0; 1 + 8 + 16][..]] {
+        let mut tampered = wire[..CHUNK_LEN].to_vec();
+        tampered.extend_from_slice(injected);
+        let mut receiver = receiver(tampered);
+        assert_eq!(receiver.next().await.unwrap().unwrap(), b"first");
+        assert!(matches!(receiver.next().await, Some(Err(_))));
+    }
+}
+
+#[tokio::test]
+async fn control_frames_are_accepted_once_across_paths() {
+    let wire = sealed().await;
+    let first = &wire[CHUNK_LEN..CHUNK_LEN + PING_LEN];
+    let second = &wire[CHUNK_LEN + PING_LEN..CHUNK_LEN + 2 * PING_LEN];
+    let (a, mut b): (Vec<_>, Vec<_>) = (0..2).map(|_| tokio::io::duplex(1 << 16)).unzip();
+    let mut receiver = MultipathChunkIO::<_, _>::new(a, &builder(2, 1), RoundRobin::default());
+
+    // Paths may deliver control frames out of order.
+    b[1].write_all(second).await.unwrap();
+    assert!(receiver.next().now_or_never().is_none());
+    b[0].write_all(first).await.unwrap();
+    assert!(receiver.next().now_or_never().is_none());
+
+    // But one sent on a path is not accepted again on another.
+    b[1].write_all(first).await.unwrap();
+    assert!(matches!(
+        receiver.next().await,
+        Some(Err(ChunkIOError::AuthenticationFailed { .. }))
+    ));
+}
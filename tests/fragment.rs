@@ -0,0 +1,62 @@
+use chunkio::{ChunkIOBuilder, ChunkIOError, MultipathChunkIO, RoundRobin};
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+
+#[tokio::test]
+async fn reassembles_fragmented_messages() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let mut a = ChunkIOBuilder::new().fragment_size(500).build(a);
+    let mut b = ChunkIOBuilder::new().build(b);
+
+    let message = (0..3000).map(|i| i as u8).collect::<Vec<_>>();
+    a.send(message.clone()).await.unwrap();
+    a.send(vec![1; 10]).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), message);
+    assert_eq!(b.next().await.unwrap().unwrap(), vec![1; 10]);
+}
+
+#[tokio::test]
+async fn stops_at_message_limit_mid_message() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let mut a = ChunkIOBuilder::new().fragment_size(500).build(a);
+    let mut b = ChunkIOBuilder::new().max_message_length(1000).build(b);
+
+    a.send(vec![1; 100]).await.unwrap();
+    a.send(vec![2; 1500]).await.unwrap();
+    a.send(vec![3; 10]).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), vec![1; 100]);
+    assert!(matches!(
+        b.next().await,
+        Some(Err(ChunkIOError::MessageTooLarge { max: 1000, .. }))
+    ));
+    // Nothing after the failed message is delivered.
+    while let Some(item) = b.next().await {
+        assert!(item.is_err());
+    }
+}
+
+#[tokio::test]
+async fn multipath_stops_at_message_limit_mid_message() {
+    let (a1, b1) = tokio::io::duplex(1 << 16);
+    let (a2, b2) = tokio::io::duplex(1 << 16);
+    let mut a: MultipathChunkIO<DuplexStream, RoundRobin> = MultipathChunkIO::new(
+        vec![a1, a2],
+        &ChunkIOBuilder::new().fragment_size(500),
+        RoundRobin::default(),
+    );
+    let mut b: MultipathChunkIO<DuplexStream, RoundRobin> = MultipathChunkIO::new(
+        vec![b1, b2],
+        &ChunkIOBuilder::new().max_message_length(1000),
+        RoundRobin::default(),
+    );
+
+    a.send(vec![1; 100]).await.unwrap();
+    a.send(vec![2; 1500]).await.unwrap();
+    a.send(vec![3; 10]).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), vec![1; 100]);
+    assert!(matches!(
+        b.next().await,
+        Some(Err(ChunkIOError::MessageTooLarge { max: 1000, .. }))
+    ));
+    assert!(b.next().await.is_none());
+}
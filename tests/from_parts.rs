@@ -0,0 +1,29 @@
+use chunkio::ChunkIO;
+use futures::{SinkExt, StreamExt};
+use tokio::io::AsyncReadExt;
+
+#[tokio::test]
+async fn speaks_over_a_pair_of_pipes() {
+    let (a_in, b_out) = tokio::io::duplex(1 << 16);
+    let (a_out, b_in) = tokio::io::duplex(1 << 16);
+    let mut a = ChunkIO::from_parts(a_in, a_out);
+    let mut b = ChunkIO::from_parts(b_in, b_out);
+    a.send(b"request".to_vec()).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), b"request");
+    b.send(b"response".to_vec()).await.unwrap();
+    assert_eq!(a.next().await.unwrap().unwrap(), b"response");
+    a.close().await.unwrap();
+    assert!(b.next().await.is_none());
+}
+
+#[tokio::test]
+async fn writes_only_to_the_writer() {
+    let (reader, _) = tokio::io::duplex(1024);
+    let (writer, mut wire) = tokio::io::duplex(1024);
+    let mut io = ChunkIO::from_parts(reader, writer);
+    io.send(b"abc".to_vec()).await.unwrap();
+    drop(io);
+    let mut buf = Vec::new();
+    wire.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, [0x01, 0x03, b'a', b'b', b'c']);
+}
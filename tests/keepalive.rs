@@ -0,0 +1,40 @@
+use std::{
+    future::poll_fn,
+    time::{Duration, Instant},
+};
+
+use chunkio::{ChunkIO, ChunkIOBuilder, ChunkIOError, ControlFrame};
+use futures::{FutureExt, StreamExt};
+
+fn builder() -> ChunkIOBuilder {
+    ChunkIOBuilder::new().keepalive(Duration::from_millis(20), Duration::from_millis(20))
+}
+
+#[tokio::test]
+async fn silent_peer_times_out() {
+    let (a, _b) = tokio::io::duplex(8192);
+    let mut a = builder().build(a);
+    let started = Instant::now();
+    assert!(matches!(a.next().await, Some(Err(ChunkIOError::Timeout))));
+    assert!(started.elapsed() >= Duration::from_millis(40));
+}
+
+#[tokio::test]
+async fn answered_pings_keep_the_connection_alive() {
+    let (a, b) = tokio::io::duplex(8192);
+    let mut a = builder().build(a);
+    let mut b = ChunkIO::new(b);
+    let reader = tokio::spawn(async move {
+        let mut pings = 0;
+        while let Some(Ok(_)) = b.next().await {}
+        while let Some(frame) = poll_fn(|cx| b.poll_control(cx)).now_or_never() {
+            assert!(matches!(frame, ControlFrame::Ping(_)));
+            pings += 1;
+        }
+        pings
+    });
+    let idle = tokio::time::timeout(Duration::from_millis(200), a.next()).await;
+    assert!(idle.is_err());
+    drop(a);
+    assert!(reader.await.unwrap() > 2);
+}
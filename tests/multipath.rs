@@ -0,0 +1,99 @@
+use chunkio::{ChunkIOBuilder, MultipathChunkIO, PathInfo, RoundRobin, Scheduler, Weighted};
+use futures::{FutureExt, SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+type Multipath<S> = MultipathChunkIO<DuplexStream, S>;
+
+fn transports(paths: usize) -> (Vec<DuplexStream>, Vec<DuplexStream>) {
+    (0..paths).map(|_| tokio::io::duplex(1 << 20)).unzip()
+}
+
+fn chunks() -> Vec<Vec<u8>> {
+    (0..40u8).map(|i| vec![i; 100]).collect()
+}
+
+// Sends on one path only.
+struct Only(usize);
+
+impl Scheduler for Only {
+    fn select(&mut self, paths: &[PathInfo], _len: usize) -> Option<usize> {
+        paths[self.0].ready.then_some(self.0)
+    }
+}
+
+fn sent<S: Scheduler>(multipath: &Multipath<S>) -> Vec<u64> {
+    multipath.paths().iter().map(|path| path.sent).collect()
+}
+
+async fn send_all<S: Scheduler + Unpin>(sender: &mut Multipath<S>) {
+    for chunk in chunks() {
+        sender.feed(chunk).await.unwrap();
+    }
+    sender.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn stripes_chunks_across_paths() {
+    let (a, b) = transports(3);
+    let builder = ChunkIOBuilder::new();
+    let mut sender = Multipath::new(a, &builder, RoundRobin::default());
+    let mut receiver = Multipath::new(b, &builder, RoundRobin::default());
+    send_all(&mut sender).await;
+    for chunk in chunks() {
+        assert_eq!(receiver.next().await.unwrap().unwrap(), chunk);
+    }
+    assert!(receiver.next().await.is_none());
+    assert_eq!(sent(&sender), [1400, 1300, 1300]);
+}
+
+#[tokio::test]
+async fn scheduler_picks_the_path() {
+    let (a, _b) = transports(2);
+    let mut sender = Multipath::new(a, &ChunkIOBuilder::new(), Weighted::new(vec![3, 1]));
+    send_all(&mut sender).await;
+    assert_eq!(sent(&sender), [3000, 1000]);
+
+    let (a, _b) = transports(2);
+    let mut sender = Multipath::new(a, &ChunkIOBuilder::new(), Only(1));
+    send_all(&mut sender).await;
+    assert_eq!(sent(&sender), [0, 4000]);
+}
+
+#[tokio::test]
+async fn reorders_chunks_from_a_late_path() {
+    let (a, mut b) = transports(2);
+    let builder = ChunkIOBuilder::new();
+    let mut sender = Multipath::new(a, &builder, RoundRobin::default());
+    send_all(&mut sender).await;
+    let mut wires = Vec::new();
+    for b in b.iter_mut() {
+        let mut wire = Vec::new();
+        b.read_to_end(&mut wire).await.unwrap();
+        wires.push(wire);
+    }
+
+    // Everything sent on the second path arrives first.
+    let (c, mut d) = transports(2);
+    let mut receiver = Multipath::new(c, &builder, RoundRobin::default());
+    d[1].write_all(&wires[1]).await.unwrap();
+    assert!(receiver.next().now_or_never().is_none());
+    d[0].write_all(&wires[0]).await.unwrap();
+    for chunk in chunks() {
+        assert_eq!(receiver.next().await.unwrap().unwrap(), chunk);
+    }
+}
+
+#[tokio::test]
+async fn losing_a_path_fails_the_stream() {
+    let (a, mut b) = transports(2);
+    let mut sender = Multipath::new(a, &ChunkIOBuilder::new(), RoundRobin::default());
+    drop(b.remove(0));
+    let mut failed = false;
+    for chunk in chunks() {
+        if sender.send(chunk).await.is_err() {
+            failed = true;
+            break;
+        }
+    }
+    assert!(failed);
+}
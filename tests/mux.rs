@@ -0,0 +1,215 @@
+use chunkio::{ChunkIOBuilder, ChunkIOError, Mux, MuxRole, MuxStream, DEFAULT_STREAM_WINDOW};
+use futures::{FutureExt, SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+use tokio_util::bytes::Bytes;
+
+fn pair() -> (Mux<DuplexStream>, Mux<DuplexStream>) {
+    let (a, b) = tokio::io::duplex(8192);
+    (
+        Mux::new(ChunkIOBuilder::new().build_bytes(a), MuxRole::Client),
+        Mux::new(ChunkIOBuilder::new().build_bytes(b), MuxRole::Server),
+    )
+}
+
+#[tokio::test]
+async fn streams_run_concurrently() {
+    let (client, server) = pair();
+    let echo = tokio::spawn(async move {
+        let mut streams = Vec::new();
+        while let Some(stream) = server.accept_stream().await {
+            let mut stream = stream.unwrap();
+            streams.push(tokio::spawn(async move {
+                while let Some(message) = stream.next().await {
+                    stream.send(message.unwrap()).await.unwrap();
+                }
+                stream.close().await.unwrap();
+            }));
+        }
+        for stream in streams {
+            stream.await.unwrap();
+        }
+    });
+
+    let mut streams = Vec::new();
+    for i in 0..20u8 {
+        let mut stream = client.open_stream().unwrap();
+        streams.push(tokio::spawn(async move {
+            for j in 0..50u8 {
+                // Every tenth message is empty.
+                let message = vec![i; (j % 10) as usize * 100];
+                stream.send(message.clone()).await.unwrap();
+                assert_eq!(stream.next().await.unwrap().unwrap(), message);
+            }
+            stream.close().await.unwrap();
+            assert!(stream.next().await.is_none());
+            stream.current_index()
+        }));
+    }
+    for stream in streams {
+        let (sent, received) = stream.await.unwrap();
+        assert_eq!(sent, 22_500);
+        assert_eq!(received, 22_500);
+    }
+    drop(client);
+    echo.await.unwrap();
+}
+
+#[tokio::test]
+async fn fin_closes_one_direction_of_one_stream() {
+    let (client, server) = pair();
+    let mut a1 = client.open_stream().unwrap();
+    let mut a2 = client.open_stream().unwrap();
+    a1.send(b"one".to_vec()).await.unwrap();
+    a2.send(b"two".to_vec()).await.unwrap();
+    let mut b1 = server.accept_stream().await.unwrap().unwrap();
+    let mut b2 = server.accept_stream().await.unwrap().unwrap();
+
+    a1.close().await.unwrap();
+    assert!(matches!(
+        a1.send(b"late".to_vec()).await,
+        Err(ChunkIOError::StreamClosed)
+    ));
+    assert_eq!(b1.next().await.unwrap().unwrap(), b"one");
+    assert!(b1.next().await.is_none());
+    assert_eq!(b2.next().await.unwrap().unwrap(), b"two");
+
+    // The other direction stays open.
+    b1.send(b"reply".to_vec()).await.unwrap();
+    assert_eq!(a1.next().await.unwrap().unwrap(), b"reply");
+}
+
+#[tokio::test]
+async fn dropping_a_stream_resets_it() {
+    let (client, server) = pair();
+    let mut a1 = client.open_stream().unwrap();
+    let mut a2 = client.open_stream().unwrap();
+    a1.send(b"one".to_vec()).await.unwrap();
+    let mut b1 = server.accept_stream().await.unwrap().unwrap();
+    assert_eq!(b1.next().await.unwrap().unwrap(), b"one");
+
+    drop(a1);
+    // The reset goes out with the next flush of any stream.
+    a2.send(b"two".to_vec()).await.unwrap();
+    assert!(matches!(b1.next().await, Some(Err(ChunkIOError::Reset))));
+    assert!(matches!(
+        b1.send(b"late".to_vec()).await,
+        Err(ChunkIOError::Reset)
+    ));
+    let mut b2 = server.accept_stream().await.unwrap().unwrap();
+    assert_eq!(b2.next().await.unwrap().unwrap(), b"two");
+}
+
+fn windowed_pair(window: u32) -> (Mux<DuplexStream>, Mux<DuplexStream>) {
+    let (a, b) = tokio::io::duplex(1 << 20);
+    (
+        Mux::with_window(
+            ChunkIOBuilder::new().build_bytes(a),
+            MuxRole::Client,
+            window,
+        ),
+        Mux::with_window(
+            ChunkIOBuilder::new().build_bytes(b),
+            MuxRole::Server,
+            window,
+        ),
+    )
+}
+
+// Sends 1000-byte items until the stream runs out of credit.
+fn send_until_blocked(stream: &mut MuxStream<DuplexStream>) -> usize {
+    let mut sent = 0;
+    while stream.send(vec![1; 1000]).now_or_never().is_some() {
+        sent += 1;
+    }
+    sent
+}
+
+#[tokio::test]
+async fn opener_sends_before_the_peer_answers() {
+    let (client, _server) = windowed_pair(10_000);
+    let mut stream = client.open_stream().unwrap();
+    stream
+        .send(vec![1; DEFAULT_STREAM_WINDOW as usize])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn sender_stops_at_the_window() {
+    let (client, server) = windowed_pair(10_000);
+    let mut a = client.open_stream().unwrap();
+    a.flush().await.unwrap();
+    let mut b = server.accept_stream().await.unwrap().unwrap();
+    assert_eq!(send_until_blocked(&mut b), 10);
+
+    // Reading half the window grants it again.
+    for _ in 0..5 {
+        assert_eq!(a.next().await.unwrap().unwrap().len(), 1000);
+    }
+    assert_eq!(send_until_blocked(&mut b), 5);
+}
+
+#[tokio::test]
+async fn slow_reader_does_not_block_other_streams() {
+    let (client, server) = windowed_pair(10_000);
+    let mut a1 = client.open_stream().unwrap();
+    let mut a2 = client.open_stream().unwrap();
+    a1.flush().await.unwrap();
+    a2.flush().await.unwrap();
+    let mut b1 = server.accept_stream().await.unwrap().unwrap();
+    let mut b2 = server.accept_stream().await.unwrap().unwrap();
+    assert_eq!(send_until_blocked(&mut b1), 10);
+
+    for i in 0..100u32 {
+        b2.send(i.to_be_bytes().to_vec()).await.unwrap();
+        assert_eq!(a2.next().await.unwrap().unwrap(), i.to_be_bytes());
+    }
+    for _ in 0..10 {
+        assert_eq!(a1.next().await.unwrap().unwrap(), vec![1; 1000]);
+    }
+}
+
+#[tokio::test]
+async fn items_larger_than_the_credit_arrive_in_pieces() {
+    let (client, server) = windowed_pair(10_000);
+    let mut a = client.open_stream().unwrap();
+    a.flush().await.unwrap();
+    let mut b = server.accept_stream().await.unwrap().unwrap();
+    let writer = tokio::spawn(async move {
+        b.send(vec![2; 25_000]).await.unwrap();
+        b.send(Vec::new()).await.unwrap();
+        b.close().await.unwrap();
+        b
+    });
+    let mut received = Vec::new();
+    let mut lens = Vec::new();
+    while let Some(piece) = a.next().await {
+        let piece = piece.unwrap();
+        lens.push(piece.len());
+        received.extend_from_slice(&piece);
+    }
+    assert_eq!(received, vec![2; 25_000]);
+    assert!(lens.len() > 2);
+    assert_eq!(lens.last(), Some(&0));
+    writer.await.unwrap();
+}
+
+#[tokio::test]
+async fn rejects_data_beyond_the_window() {
+    let (a, b) = tokio::io::duplex(1 << 20);
+    let server = Mux::new(ChunkIOBuilder::new().build_bytes(b), MuxRole::Server);
+    let mut raw = ChunkIOBuilder::new().build_bytes(a);
+    // OPEN for stream 1, then one byte more than the default credit.
+    raw.send(Bytes::from_static(&[0x10, 0, 0, 0, 1]))
+        .await
+        .unwrap();
+    let mut data = vec![0x00, 0, 0, 0, 1];
+    data.resize(5 + DEFAULT_STREAM_WINDOW as usize + 1, 0);
+    raw.send(Bytes::from(data)).await.unwrap();
+
+    let mut stream = server.accept_stream().await.unwrap().unwrap();
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(ChunkIOError::FlowControl))
+    ));
+}
@@ -0,0 +1,128 @@
+use chunkio::{ChunkIO, ChunkIOBuilder, ChunkIOError, Mux, MuxRole, PROTOCOL_VERSION};
+use futures::{SinkExt, StreamExt};
+use tokio::io::AsyncWriteExt;
+
+// A hello from a peer speaking versions `min..=max` with control frames and
+// no checksum or compression.
+fn hello(magic: &[u8; 4], max: u8, min: u8) -> Vec<u8> {
+    let mut hello = magic.to_vec();
+    hello.extend_from_slice(&[max, min, 0x1, 0x1, 0, 0x1, 0, 0, 0]);
+    hello
+}
+
+async fn negotiate_with(hello: Vec<u8>) -> ChunkIOError {
+    let (a, mut b) = tokio::io::duplex(1 << 16);
+    b.write_all(&hello).await.unwrap();
+    ChunkIO::negotiate(a, &ChunkIOBuilder::new())
+        .await
+        .map(|_| ())
+        .unwrap_err()
+}
+
+#[tokio::test]
+async fn negotiates_a_stream() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let builder = ChunkIOBuilder::new();
+    let (a, b) = tokio::join!(
+        ChunkIO::negotiate(a, &builder),
+        ChunkIO::negotiate(b, &builder)
+    );
+    let (mut a, negotiated) = a.unwrap();
+    let (mut b, _) = b.unwrap();
+    assert_eq!(negotiated.version, PROTOCOL_VERSION);
+    assert!(negotiated.capabilities.control_frames);
+    assert!(!negotiated.capabilities.mux);
+    assert!(a.codec().end_of_stream());
+    a.send(b"hello".to_vec()).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn leaves_out_the_end_of_the_stream_for_version_1_peers() {
+    let (a, mut b) = tokio::io::duplex(1 << 16);
+    b.write_all(&hello(b"CKIO", 1, 1)).await.unwrap();
+    let (io, negotiated) = ChunkIO::negotiate(a, &ChunkIOBuilder::new()).await.unwrap();
+    assert_eq!(negotiated.version, 1);
+    assert!(!io.codec().end_of_stream());
+}
+
+#[tokio::test]
+async fn rejects_a_bad_magic() {
+    let error = negotiate_with(hello(b"CKIX", PROTOCOL_VERSION, 1)).await;
+    assert!(matches!(error, ChunkIOError::InvalidHello));
+}
+
+#[tokio::test]
+async fn rejects_versions_without_overlap() {
+    let error = negotiate_with(hello(b"CKIO", PROTOCOL_VERSION + 2, PROTOCOL_VERSION + 1)).await;
+    assert!(matches!(error, ChunkIOError::UnsupportedVersion(v) if v == PROTOCOL_VERSION + 2));
+    let error = negotiate_with(hello(b"CKIO", 0, 0)).await;
+    assert!(matches!(error, ChunkIOError::UnsupportedVersion(0)));
+}
+
+#[tokio::test]
+async fn negotiates_a_mux() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let builder = ChunkIOBuilder::new();
+    let (a, b) = tokio::join!(
+        Mux::negotiate(a, &builder, MuxRole::Client),
+        Mux::negotiate(b, &builder, MuxRole::Server)
+    );
+    let (a, negotiated) = a.unwrap();
+    let (b, _) = b.unwrap();
+    assert!(negotiated.capabilities.mux);
+    let mut stream = a.open_stream().unwrap();
+    stream.send(b"hello".to_vec()).await.unwrap();
+    let mut accepted = b.accept_stream().await.unwrap().unwrap();
+    assert_eq!(accepted.next().await.unwrap().unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn mux_and_plain_peers_disagree() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let builder = ChunkIOBuilder::new();
+    let (a, b) = tokio::join!(
+        Mux::negotiate(a, &builder, MuxRole::Client),
+        ChunkIO::negotiate(b, &builder)
+    );
+    assert!(matches!(a, Err(ChunkIOError::CapabilityMismatch("mux"))));
+    assert!(matches!(b, Err(ChunkIOError::CapabilityMismatch("mux"))));
+}
+
+#[cfg(feature = "noise")]
+#[tokio::test]
+async fn negotiates_a_handshake() {
+    use chunkio::NoiseConfig;
+
+    let (a_private, _) = NoiseConfig::generate_keypair().unwrap();
+    let (b_private, b_public) = NoiseConfig::generate_keypair().unwrap();
+    let initiator = NoiseConfig::initiator(a_private);
+    let responder = NoiseConfig::responder(b_private);
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let (a, b) = tokio::join!(
+        ChunkIO::negotiate_handshake(a, &initiator),
+        ChunkIO::negotiate_handshake(b, &responder)
+    );
+    let (mut a, remote, negotiated) = a.unwrap();
+    let (mut b, _, _) = b.unwrap();
+    assert_eq!(remote, b_public);
+    assert!(negotiated.capabilities.encryption);
+    assert!(a.codec().cipher().is_some());
+    a.send(b"hello".to_vec()).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), b"hello");
+
+    let builder = ChunkIOBuilder::new();
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let (a, b) = tokio::join!(
+        ChunkIO::negotiate_handshake(a, &initiator),
+        ChunkIO::negotiate(b, &builder)
+    );
+    assert!(matches!(
+        a,
+        Err(ChunkIOError::CapabilityMismatch("encryption"))
+    ));
+    assert!(matches!(
+        b,
+        Err(ChunkIOError::CapabilityMismatch("encryption"))
+    ));
+}
@@ -0,0 +1,101 @@
+#![cfg(feature = "noise")]
+
+use chunkio::{ChunkIO, ChunkIOBuilder, ChunkIOError, Cipher, NoiseConfig, NoisePattern};
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+
+type Session = Result<(ChunkIO<DuplexStream>, [u8; 32]), ChunkIOError>;
+
+async fn handshake(initiator: NoiseConfig, responder: NoiseConfig) -> (Session, Session) {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    tokio::join!(
+        ChunkIO::handshake(a, &initiator),
+        ChunkIO::handshake(b, &responder)
+    )
+}
+
+async fn exchange(a: &mut ChunkIO<DuplexStream>, b: &mut ChunkIO<DuplexStream>) {
+    a.send(b"ping".to_vec()).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), b"ping");
+    b.send(b"pong".to_vec()).await.unwrap();
+    assert_eq!(a.next().await.unwrap().unwrap(), b"pong");
+}
+
+#[tokio::test]
+async fn xx_exchanges_static_keys() {
+    let (a_private, a_public) = NoiseConfig::generate_keypair().unwrap();
+    let (b_private, b_public) = NoiseConfig::generate_keypair().unwrap();
+    // Keys set on the builder are replaced by the negotiated ones.
+    let builder = ChunkIOBuilder::new().encryption(Cipher::ChaCha20Poly1305, [1; 32], [2; 32]);
+    let (a, b) = handshake(
+        NoiseConfig::initiator(a_private).builder(builder.clone()),
+        NoiseConfig::responder(b_private).builder(builder),
+    )
+    .await;
+    let (mut a, a_remote) = a.unwrap();
+    let (mut b, b_remote) = b.unwrap();
+    assert_eq!(a_remote, b_public);
+    assert_eq!(b_remote, a_public);
+    assert_eq!(a.codec().cipher(), Some(Cipher::ChaCha20Poly1305));
+    exchange(&mut a, &mut b).await;
+}
+
+#[tokio::test]
+async fn ik_uses_the_known_responder_key() {
+    let (a_private, a_public) = NoiseConfig::generate_keypair().unwrap();
+    let (b_private, b_public) = NoiseConfig::generate_keypair().unwrap();
+    let (a, b) = handshake(
+        NoiseConfig::initiator(a_private)
+            .pattern(NoisePattern::Ik)
+            .remote_public_key(b_public),
+        NoiseConfig::responder(b_private).pattern(NoisePattern::Ik),
+    )
+    .await;
+    let (mut a, a_remote) = a.unwrap();
+    let (mut b, b_remote) = b.unwrap();
+    assert_eq!(a_remote, b_public);
+    assert_eq!(b_remote, a_public);
+    exchange(&mut a, &mut b).await;
+}
+
+#[tokio::test]
+async fn ik_rejects_the_wrong_responder_key() {
+    let (a_private, _) = NoiseConfig::generate_keypair().unwrap();
+    let (b_private, _) = NoiseConfig::generate_keypair().unwrap();
+    let (_, other_public) = NoiseConfig::generate_keypair().unwrap();
+    let (a, b) = handshake(
+        NoiseConfig::initiator(a_private)
+            .pattern(NoisePattern::Ik)
+            .remote_public_key(other_public),
+        NoiseConfig::responder(b_private).pattern(NoisePattern::Ik),
+    )
+    .await;
+    assert!(matches!(b, Err(ChunkIOError::Handshake(_))));
+    assert!(a.is_err());
+}
+
+#[tokio::test]
+async fn rejects_a_tampered_handshake_message() {
+    let (a_private, _) = NoiseConfig::generate_keypair().unwrap();
+    let (b_private, _) = NoiseConfig::generate_keypair().unwrap();
+    let (a, a_relay) = tokio::io::duplex(1 << 16);
+    let (b, b_relay) = tokio::io::duplex(1 << 16);
+    let responder = NoiseConfig::responder(b_private);
+    let responder = tokio::spawn(async move { ChunkIO::handshake(b, &responder).await.is_ok() });
+    // Passes the first message on and flips a bit of the reply, which carries
+    // the responder's encrypted static key.
+    let relay = tokio::spawn(async move {
+        let mut a_relay = ChunkIO::new(a_relay);
+        let mut b_relay = ChunkIO::new(b_relay);
+        let first = a_relay.next().await.unwrap().unwrap();
+        b_relay.send(first).await.unwrap();
+        let mut second = b_relay.next().await.unwrap().unwrap();
+        *second.last_mut().unwrap() ^= 1;
+        a_relay.send(second).await.unwrap();
+        (a_relay, b_relay)
+    });
+    let a = ChunkIO::handshake(a, &NoiseConfig::initiator(a_private)).await;
+    assert!(matches!(a, Err(ChunkIOError::Handshake(_))));
+    drop(relay.await.unwrap());
+    assert!(!responder.await.unwrap());
+}
@@ -0,0 +1,132 @@
+use chunkio::{ChunkIOBuilder, ChunkIOError, ChunkIOProto, Frame};
+use tokio_util::{
+    bytes::{Bytes, BytesMut},
+    codec::Decoder,
+};
+
+// A data chunk sent at `index`.
+fn chunk(index: u64, payload: &[u8]) -> Vec<u8> {
+    let index = index.to_be_bytes();
+    let index = &index[index.iter().take_while(|b| **b == 0).count()..];
+    let length = (payload.len() as u64).to_be_bytes();
+    let length = &length[length.iter().take_while(|b| **b == 0).count()..];
+    let mut frame = vec![((index.len() as u8) << 4) | length.len() as u8];
+    frame.extend_from_slice(index);
+    frame.extend_from_slice(length);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// Feeds `frames` one at a time and returns the payloads delivered in order.
+fn receive(codec: &mut ChunkIOProto, frames: &[Vec<u8>]) -> Result<Vec<Bytes>, ChunkIOError> {
+    let mut delivered = Vec::new();
+    let mut src = BytesMut::new();
+    for frame in frames {
+        src.extend_from_slice(frame);
+        while let Some(frame) = codec.decode(&mut src)? {
+            let Frame::Data(chunk) = frame else {
+                panic!("expected a chunk");
+            };
+            delivered.push(chunk.payload);
+        }
+    }
+    Ok(delivered)
+}
+
+#[test]
+fn delivers_out_of_order_chunks_in_order() {
+    let mut codec = ChunkIOBuilder::new().reorder_buffer(1024).codec();
+    let delivered = receive(
+        &mut codec,
+        &[chunk(4, b"ef"), chunk(2, b"cd"), chunk(0, b"ab")],
+    )
+    .unwrap();
+    assert_eq!(delivered, [&b"ab"[..], b"cd", b"ef"]);
+    assert_eq!(codec.current_index().1, 6);
+}
+
+#[test]
+fn drops_duplicates() {
+    let mut codec = ChunkIOBuilder::new().reorder_buffer(1024).codec();
+    let delivered = receive(
+        &mut codec,
+        &[
+            chunk(0, b"ab"),
+            chunk(0, b"ab"),
+            chunk(4, b"ef"),
+            chunk(4, b"ef"),
+            chunk(2, b"cd"),
+            chunk(2, b"cd"),
+        ],
+    )
+    .unwrap();
+    assert_eq!(delivered, [&b"ab"[..], b"cd", b"ef"]);
+}
+
+#[test]
+fn rejects_overlapping_chunks() {
+    let mut codec = ChunkIOBuilder::new().reorder_buffer(1024).codec();
+    // Runs past the receive offset.
+    assert!(matches!(
+        receive(&mut codec, &[chunk(0, b"a"), chunk(0, b"ab")]),
+        Err(ChunkIOError::OutOfOrder)
+    ));
+
+    // Starts where a held chunk of another length does.
+    let mut codec = ChunkIOBuilder::new().reorder_buffer(1024).codec();
+    assert!(matches!(
+        receive(&mut codec, &[chunk(4, b"ef"), chunk(4, b"e")]),
+        Err(ChunkIOError::OutOfOrder)
+    ));
+}
+
+#[test]
+fn bounds_the_held_bytes() {
+    let mut codec = ChunkIOBuilder::new().reorder_buffer(4).codec();
+    assert!(matches!(
+        receive(
+            &mut codec,
+            &[chunk(2, b"cd"), chunk(4, b"ef"), chunk(6, b"g")]
+        ),
+        Err(ChunkIOError::ReassemblyFull)
+    ));
+}
+
+#[test]
+fn rejects_gaps_without_a_reorder_buffer() {
+    let mut codec = ChunkIOBuilder::new().codec();
+    assert!(matches!(
+        receive(&mut codec, &[chunk(2, b"cd")]),
+        Err(ChunkIOError::OutOfOrder)
+    ));
+}
+
+#[test]
+fn delivers_a_held_empty_chunk_before_data_at_its_offset() {
+    let mut codec = ChunkIOBuilder::new().reorder_buffer(1024).codec();
+    let delivered = receive(
+        &mut codec,
+        &[chunk(2, b""), chunk(2, b"cd"), chunk(0, b"ab")],
+    )
+    .unwrap();
+    assert_eq!(delivered, [&b"ab"[..], b"", b"cd"]);
+}
+
+#[test]
+fn keeps_one_of_the_empty_chunks_held_at_an_offset() {
+    // Empty chunks at one offset look like duplicates of each other, so only
+    // the first one held is delivered; those at the receive offset all are.
+    let mut codec = ChunkIOBuilder::new().reorder_buffer(1024).codec();
+    let delivered = receive(
+        &mut codec,
+        &[
+            chunk(0, b""),
+            chunk(2, b""),
+            chunk(2, b""),
+            chunk(0, b""),
+            chunk(0, b"ab"),
+        ],
+    )
+    .unwrap();
+    assert_eq!(delivered, [&b""[..], b"", b"ab", b""]);
+}
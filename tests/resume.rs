@@ -0,0 +1,71 @@
+use chunkio::{ChunkIOBuilder, ResumableChunkIO};
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+
+type Session = ResumableChunkIO<DuplexStream>;
+
+fn session(retransmit_capacity: usize) -> Session {
+    ResumableChunkIO::new(ChunkIOBuilder::new(), retransmit_capacity)
+}
+
+async fn connect(a: &mut Session, b: &mut Session) {
+    let (x, y) = tokio::io::duplex(4096);
+    let (a, b) = tokio::join!(a.resume(x), b.resume(y));
+    a.unwrap();
+    b.unwrap();
+}
+
+#[tokio::test]
+async fn replays_unreceived_chunks_after_reconnect() {
+    let (mut a, mut b) = (session(1024), session(1024));
+    connect(&mut a, &mut b).await;
+    for i in 0..10 {
+        a.send(vec![i; 10]).await.unwrap();
+    }
+    for i in 0..3 {
+        assert_eq!(b.next().await.unwrap().unwrap(), vec![i; 10]);
+    }
+
+    // Whatever the old transport still held for `b` is replayed.
+    connect(&mut a, &mut b).await;
+    for i in 3..10 {
+        assert_eq!(b.next().await.unwrap().unwrap(), vec![i; 10]);
+    }
+    a.close().await.unwrap();
+    assert!(b.next().await.is_none());
+}
+
+#[tokio::test]
+async fn acknowledgements_are_not_part_of_the_session() {
+    let (mut a, mut b) = (session(64), session(64));
+    connect(&mut a, &mut b).await;
+    // A retransmit capacity of 64 makes both sides acknowledge every 32
+    // bytes, in both directions at once.
+    for i in 0..40 {
+        a.send(vec![i; 10]).await.unwrap();
+        b.send(vec![i; 10]).await.unwrap();
+        assert_eq!(b.next().await.unwrap().unwrap(), vec![i; 10]);
+        assert_eq!(a.next().await.unwrap().unwrap(), vec![i; 10]);
+    }
+    assert_eq!(a.current_index(), (440, 440));
+    assert_eq!(b.current_index(), (440, 440));
+
+    connect(&mut a, &mut b).await;
+    a.send(vec![0xaa]).await.unwrap();
+    b.send(vec![0xbb]).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), vec![0xaa]);
+    assert_eq!(a.next().await.unwrap().unwrap(), vec![0xbb]);
+    assert_eq!(a.current_index(), (442, 442));
+    assert_eq!(b.current_index(), (442, 442));
+}
+
+#[tokio::test]
+async fn reports_disconnect_until_resumed() {
+    let (mut a, mut b) = (session(1024), session(1024));
+    assert!(a.send(vec![1]).await.is_err());
+    connect(&mut a, &mut b).await;
+    a.send(vec![1]).await.unwrap();
+    drop(b);
+    assert!(a.next().await.unwrap().is_err());
+    assert!(!a.is_connected());
+}
@@ -0,0 +1,87 @@
+use std::future::poll_fn;
+
+use chunkio::{ChunkIO, ChunkIOError, ControlFrame, ReuniteError};
+use futures::{SinkExt, StreamExt};
+
+#[tokio::test]
+async fn halves_work_from_different_tasks() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let (mut reader, mut writer) = ChunkIO::new(a).into_split();
+    let mut b = ChunkIO::new(b);
+    let writing = tokio::spawn(async move {
+        for i in 0..10u8 {
+            writer.send(vec![i; 10]).await.unwrap();
+        }
+        writer
+    });
+    let reading = tokio::spawn(async move {
+        for i in 0..10u8 {
+            assert_eq!(reader.next().await.unwrap().unwrap(), vec![i; 10]);
+        }
+        reader
+    });
+    for i in 0..10u8 {
+        assert_eq!(b.next().await.unwrap().unwrap(), vec![i; 10]);
+        b.send(vec![i; 10]).await.unwrap();
+    }
+    let writer = writing.await.unwrap();
+    let reader = reading.await.unwrap();
+    assert_eq!(writer.codec().current_index().0, 100);
+    assert_eq!(reader.codec().current_index().1, 100);
+}
+
+#[tokio::test]
+async fn reunites_with_both_offsets() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let (mut reader, mut writer) = ChunkIO::new(a).into_split();
+    let mut b = ChunkIO::new(b);
+    writer.send(b"abc".to_vec()).await.unwrap();
+    b.send(b"de".to_vec()).await.unwrap();
+    assert_eq!(reader.next().await.unwrap().unwrap(), b"de");
+    assert!(reader.is_pair_of(&writer));
+
+    let mut a = writer.reunite(reader).unwrap();
+    assert_eq!(a.codec().current_index(), (3, 2));
+    a.send(b"fg".to_vec()).await.unwrap();
+    b.send(b"h".to_vec()).await.unwrap();
+    assert_eq!(a.next().await.unwrap().unwrap(), b"h");
+    assert_eq!(b.next().await.unwrap().unwrap(), b"abc");
+    assert_eq!(b.next().await.unwrap().unwrap(), b"fg");
+}
+
+#[tokio::test]
+async fn refuses_to_reunite_halves_of_different_streams() {
+    let (a, _a) = tokio::io::duplex(1024);
+    let (b, _b) = tokio::io::duplex(1024);
+    let (a_reader, a_writer) = ChunkIO::new(a).into_split();
+    let (b_reader, b_writer) = ChunkIO::new(b).into_split();
+    assert!(!a_reader.is_pair_of(&b_writer));
+    let Err(ReuniteError(a_reader, b_writer)) = a_reader.reunite(b_writer) else {
+        panic!("halves of different streams reunited");
+    };
+    assert!(a_reader.reunite(a_writer).is_ok());
+    assert!(b_writer.reunite(b_reader).is_ok());
+}
+
+#[tokio::test]
+async fn reader_answers_pings_through_the_writer() {
+    let (a, b) = tokio::io::duplex(1 << 16);
+    let (mut reader, writer) = ChunkIO::new(a).into_split();
+    let mut b = ChunkIO::new(b);
+    b.send_control(ControlFrame::Ping(3));
+    b.send(b"x".to_vec()).await.unwrap();
+    assert_eq!(reader.next().await.unwrap().unwrap(), b"x");
+    assert_eq!(
+        poll_fn(|cx| reader.poll_control(cx)).await,
+        ControlFrame::Ping(3)
+    );
+    drop((reader, writer));
+    assert!(matches!(
+        b.next().await,
+        Some(Err(ChunkIOError::Disconnected))
+    ));
+    assert_eq!(
+        poll_fn(|cx| b.poll_control(cx)).await,
+        ControlFrame::Pong(3)
+    );
+}
@@ -0,0 +1,120 @@
+use std::io::Cursor;
+
+use chunkio::{Chunk, ChunkIOBuilder, ChunkIOError, StreamingReader};
+use futures::{SinkExt, StreamExt};
+use tokio::io::AsyncReadExt;
+
+// Returns the bytes written for `chunks` followed by the end of the stream.
+async fn wire(chunks: Vec<Chunk>) -> Vec<u8> {
+    let mut wire = Vec::new();
+    let mut sender =
+        ChunkIOBuilder::new().build_chunks(tokio::io::join(tokio::io::empty(), &mut wire));
+    for chunk in chunks {
+        sender.send(chunk).await.unwrap();
+    }
+    sender.close().await.unwrap();
+    drop(sender);
+    wire
+}
+
+#[tokio::test]
+async fn streams_payloads_piece_by_piece() {
+    let payload = (0..10_000).map(|i| i as u8).collect::<Vec<_>>();
+    let wire = wire(vec![Chunk::new(payload.clone()), Chunk::new(&b"next"[..])]).await;
+    let builder = ChunkIOBuilder::new().read_buffer_capacity(1024);
+    let mut reader = StreamingReader::new(Cursor::new(wire), &builder);
+
+    let header = reader.next_chunk().await.unwrap().unwrap();
+    assert_eq!((header.index, header.length), (0, 10_000));
+    let mut pieces = 0;
+    let mut received = Vec::new();
+    let mut payload_stream = reader.payload();
+    while let Some(piece) = payload_stream.next().await {
+        received.extend_from_slice(&piece.unwrap());
+        pieces += 1;
+    }
+    assert!(pieces > 1);
+    assert_eq!(received, payload);
+
+    let header = reader.next_chunk().await.unwrap().unwrap();
+    assert_eq!((header.index, header.length), (10_000, 4));
+    let mut received = Vec::new();
+    reader.payload().read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, b"next");
+    assert!(reader.next_chunk().await.unwrap().is_none());
+    assert_eq!(reader.current_index(), 10_004);
+}
+
+#[tokio::test]
+async fn skips_unread_payloads() {
+    let wire = wire(vec![Chunk::new(vec![1; 5000]), Chunk::new(vec![2; 3])]).await;
+    let mut reader = StreamingReader::new(Cursor::new(wire), &ChunkIOBuilder::new());
+    reader.next_chunk().await.unwrap().unwrap();
+    let header = reader.next_chunk().await.unwrap().unwrap();
+    assert_eq!(header.index, 5000);
+    let mut received = Vec::new();
+    reader.payload().read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, [2; 3]);
+}
+
+#[tokio::test]
+async fn reads_chunks_with_metadata_whole() {
+    let chunk = Chunk::new(&b"payload"[..])
+        .with_kind(4)
+        .with_metadata("k", &b"v"[..]);
+    let wire = wire(vec![chunk]).await;
+    let mut reader = StreamingReader::new(Cursor::new(wire), &ChunkIOBuilder::new());
+    let header = reader.next_chunk().await.unwrap().unwrap();
+    assert_eq!(header.kind, 4);
+    assert_eq!(header.metadata, [("k".to_string(), "v".into())]);
+    assert_eq!(header.length, 7);
+    let mut received = Vec::new();
+    reader.payload().read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, b"payload");
+}
+
+#[tokio::test]
+async fn bounds_chunks_read_whole_separately() {
+    let wire = wire(vec![
+        Chunk::new(vec![1; 100]),
+        Chunk::new(vec![2; 100]).with_kind(1),
+    ])
+    .await;
+    let mut reader =
+        StreamingReader::with_max_buffered_length(Cursor::new(wire), &ChunkIOBuilder::new(), 50);
+    // Streamed chunks may be larger.
+    assert_eq!(reader.next_chunk().await.unwrap().unwrap().length, 100);
+    assert!(matches!(
+        reader.next_chunk().await,
+        Err(ChunkIOError::ChunkTooLarge { max: 50, .. })
+    ));
+}
+
+#[tokio::test]
+async fn reports_a_transport_ending_mid_payload() {
+    let mut wire = wire(vec![Chunk::new(vec![1; 100])]).await;
+    wire.truncate(50);
+    let mut reader = StreamingReader::new(Cursor::new(wire), &ChunkIOBuilder::new());
+    reader.next_chunk().await.unwrap().unwrap();
+    let mut received = Vec::new();
+    let error = reader
+        .payload()
+        .read_to_end(&mut received)
+        .await
+        .unwrap_err();
+    let error = error
+        .into_inner()
+        .unwrap()
+        .downcast::<ChunkIOError>()
+        .unwrap();
+    assert!(matches!(*error, ChunkIOError::Disconnected));
+}
+
+#[tokio::test]
+async fn ends_with_the_transport_without_the_end_frame() {
+    let wire = vec![0x01, 0x01, b'a'];
+    let builder = ChunkIOBuilder::new().end_of_stream(false);
+    let mut reader = StreamingReader::new(Cursor::new(wire), &builder);
+    assert_eq!(reader.next_chunk().await.unwrap().unwrap().length, 1);
+    assert!(reader.next_chunk().await.unwrap().is_none());
+}
@@ -0,0 +1,161 @@
+#![cfg(feature = "udp")]
+
+use std::net::SocketAddr;
+
+use chunkio::{ChunkIOBuilder, ChunkIOError, UdpChunkIO};
+use futures::{SinkExt, StreamExt};
+use tokio::net::UdpSocket;
+
+async fn socket_pair() -> (UdpSocket, UdpSocket) {
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    a.connect(b.local_addr().unwrap()).await.unwrap();
+    b.connect(a.local_addr().unwrap()).await.unwrap();
+    (a, b)
+}
+
+// Connects both sockets to a relay that drops every `drop_every`th datagram
+// in either direction.
+async fn lossy_pair(drop_every: u64) -> (UdpSocket, UdpSocket) {
+    let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    a.connect(relay.local_addr().unwrap()).await.unwrap();
+    b.connect(relay.local_addr().unwrap()).await.unwrap();
+    let (a_addr, b_addr): (SocketAddr, SocketAddr) =
+        (a.local_addr().unwrap(), b.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut buf = vec![0; 64 * 1024];
+        let mut count = 0u64;
+        loop {
+            let (len, from) = relay.recv_from(&mut buf).await.unwrap();
+            count += 1;
+            if count.is_multiple_of(drop_every) {
+                continue;
+            }
+            let to = if from == a_addr { b_addr } else { a_addr };
+            relay.send_to(&buf[..len], to).await.unwrap();
+        }
+    });
+    (a, b)
+}
+
+fn payload(i: u32) -> Vec<u8> {
+    i.to_be_bytes().repeat(i as usize % 64 + 1)
+}
+
+#[tokio::test]
+async fn round_trip() {
+    let (a, b) = socket_pair().await;
+    let mut a: UdpChunkIO = UdpChunkIO::new(a, &ChunkIOBuilder::new());
+    let mut b: UdpChunkIO = UdpChunkIO::new(b, &ChunkIOBuilder::new());
+
+    for i in 0..100 {
+        a.feed(payload(i)).await.unwrap();
+    }
+    a.flush().await.unwrap();
+    for i in 0..100 {
+        assert_eq!(b.next().await.unwrap().unwrap(), payload(i));
+    }
+
+    b.send(b"reply".to_vec()).await.unwrap();
+    assert_eq!(a.next().await.unwrap().unwrap(), b"reply");
+}
+
+#[tokio::test]
+async fn rejects_chunk_larger_than_datagram() {
+    let (a, _b) = socket_pair().await;
+    let mut a: UdpChunkIO = UdpChunkIO::new(a, &ChunkIOBuilder::new());
+    assert!(matches!(
+        a.send(vec![0; 2000]).await,
+        Err(ChunkIOError::ChunkTooLarge { length: 2000, .. })
+    ));
+}
+
+#[tokio::test]
+async fn retransmits_lost_datagrams() {
+    let (a, b) = lossy_pair(5).await;
+    let mut a: UdpChunkIO = UdpChunkIO::new(a, &ChunkIOBuilder::new());
+    let mut b: UdpChunkIO = UdpChunkIO::new(b, &ChunkIOBuilder::new());
+
+    let sender = tokio::spawn(async move {
+        for i in 0..500 {
+            a.send(payload(i)).await.unwrap();
+        }
+        a.close().await.unwrap();
+    });
+    let receiver = async move {
+        let mut received = 0;
+        while let Some(chunk) = b.next().await {
+            assert_eq!(chunk.unwrap(), payload(received));
+            received += 1;
+        }
+        received
+    };
+    let received = tokio::time::timeout(std::time::Duration::from_secs(30), receiver)
+        .await
+        .unwrap();
+    assert_eq!(received, 500);
+    sender.await.unwrap();
+}
+
+#[tokio::test]
+async fn close_ends_peer_stream() {
+    let (a, b) = socket_pair().await;
+    let mut a: UdpChunkIO = UdpChunkIO::new(a, &ChunkIOBuilder::new());
+    let mut b: UdpChunkIO = UdpChunkIO::new(b, &ChunkIOBuilder::new());
+
+    a.send(b"last".to_vec()).await.unwrap();
+    let closer = tokio::spawn(async move { a.close().await });
+    assert_eq!(b.next().await.unwrap().unwrap(), b"last");
+    assert!(b.next().await.is_none());
+    closer.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn tolerates_a_peer_that_binds_late() {
+    // Nothing listens on `addr` at first, so the first datagrams are refused.
+    let addr = UdpSocket::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    a.connect(addr).await.unwrap();
+    let a_addr = a.local_addr().unwrap();
+    let mut a: UdpChunkIO = UdpChunkIO::new(a, &ChunkIOBuilder::new());
+    let sender = tokio::spawn(async move {
+        a.send(b"early".to_vec()).await.unwrap();
+        a.close().await
+    });
+
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let b = UdpSocket::bind(addr).await.unwrap();
+    b.connect(a_addr).await.unwrap();
+    let mut b: UdpChunkIO = UdpChunkIO::new(b, &ChunkIOBuilder::new());
+    let receiver = async move {
+        assert_eq!(b.next().await.unwrap().unwrap(), b"early");
+        assert!(b.next().await.is_none());
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(10), receiver)
+        .await
+        .unwrap();
+    sender.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn stops_at_message_limit_mid_message() {
+    let (a, b) = socket_pair().await;
+    let mut a: UdpChunkIO = UdpChunkIO::new(a, &ChunkIOBuilder::new().fragment_size(500));
+    let mut b: UdpChunkIO = UdpChunkIO::new(b, &ChunkIOBuilder::new().max_message_length(1000));
+
+    a.send(vec![1; 100]).await.unwrap();
+    a.send(vec![2; 1500]).await.unwrap();
+    a.send(vec![3; 10]).await.unwrap();
+    assert_eq!(b.next().await.unwrap().unwrap(), vec![1; 100]);
+    assert!(matches!(
+        b.next().await,
+        Some(Err(ChunkIOError::MessageTooLarge { max: 1000, .. }))
+    ));
+    assert!(b.next().await.is_none());
+}